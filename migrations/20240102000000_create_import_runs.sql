@@ -0,0 +1,15 @@
+-- Record every attempt to import the CSV dump
+CREATE TABLE IF NOT EXISTS import_runs (
+    id BIGSERIAL PRIMARY KEY,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    finished_at TIMESTAMPTZ,
+    duration_ms BIGINT,
+    status TEXT NOT NULL DEFAULT 'running',
+    rows_imported BIGINT,
+    source_path TEXT NOT NULL,
+    source_modified_at TIMESTAMPTZ,
+    source_size BIGINT,
+    error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_import_runs_started_at ON import_runs (started_at DESC);
@@ -0,0 +1,7 @@
+-- Record how each import was applied and, for incremental imports, how many
+-- rows it changed
+ALTER TABLE import_runs
+    ADD COLUMN IF NOT EXISTS mode TEXT,
+    ADD COLUMN IF NOT EXISTS rows_inserted BIGINT,
+    ADD COLUMN IF NOT EXISTS rows_updated BIGINT,
+    ADD COLUMN IF NOT EXISTS rows_deleted BIGINT;
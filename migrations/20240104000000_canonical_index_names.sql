@@ -0,0 +1,30 @@
+-- Imports used to create the table with LIKE ... INCLUDING indexes, which
+-- gives indexes generated names. Rename them back to the names created by the
+-- initial migration so later migrations can refer to them.
+ALTER TABLE import_runs ADD COLUMN IF NOT EXISTS index_build_ms BIGINT;
+
+DO $$
+DECLARE
+    idx record;
+    canonical text;
+BEGIN
+    FOR idx IN
+        SELECT i.relname AS name, pg_get_indexdef(i.oid) AS def, x.indisprimary AS is_primary
+        FROM pg_index x
+        JOIN pg_class i ON i.oid = x.indexrelid
+        WHERE x.indrelid = '"sponsorTimes"'::regclass
+    LOOP
+        canonical := CASE
+            WHEN idx.is_primary THEN 'sponsorTimes_pkey'
+            WHEN idx.def LIKE '%USING btree ("hashedVideoID")' THEN 'idx_sponsorTimes_hashedVideoID'
+            WHEN idx.def LIKE '%USING btree ("videoID")' THEN 'idx_sponsorTimes_videoID'
+            WHEN idx.def LIKE '%USING btree (category)' THEN 'idx_sponsorTimes_category'
+            WHEN idx.def LIKE '%USING btree (hidden, "shadowHidden")' THEN 'idx_sponsorTimes_hidden_shadowHidden'
+            WHEN idx.def LIKE '%USING btree (votes)' THEN 'idx_sponsorTimes_votes'
+        END;
+
+        IF canonical IS NOT NULL AND canonical <> idx.name AND to_regclass(quote_ident(canonical)) IS NULL THEN
+            EXECUTE format('ALTER INDEX %I RENAME TO %I', idx.name, canonical);
+        END IF;
+    END LOOP;
+END $$;
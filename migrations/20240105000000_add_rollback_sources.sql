@@ -0,0 +1,6 @@
+-- A rollback restores an earlier dataset; record the dump it set aside, so
+-- that the importer doesn't load it again
+ALTER TABLE import_runs
+    ADD COLUMN IF NOT EXISTS replaced_source_path TEXT,
+    ADD COLUMN IF NOT EXISTS replaced_source_modified_at TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS replaced_source_size BIGINT;
@@ -0,0 +1,18 @@
+-- Imports used to load new datasets into unlogged tables and swap them in,
+-- which streaming standbys can't read. Make the live table, and the previous
+-- generations a rollback would swap in, logged again.
+DO $$
+DECLARE
+    tbl record;
+BEGIN
+    FOR tbl IN
+        SELECT c.relname AS name
+        FROM pg_class c
+        WHERE c.relnamespace = current_schema()::regnamespace
+            AND c.relkind = 'r'
+            AND c.relpersistence = 'u'
+            AND (c.relname = 'sponsorTimes' OR c.relname LIKE 'sponsorTimes\_prev%')
+    LOOP
+        EXECUTE format('ALTER TABLE %I SET LOGGED', tbl.name);
+    END LOOP;
+END $$;
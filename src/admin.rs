@@ -0,0 +1,157 @@
+use std::path::PathBuf;
+
+use actix_web::http::header::{AUTHORIZATION, LOCATION, WWW_AUTHENTICATE};
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use sqlx::PgPool;
+use tracing::{error, info};
+
+use crate::config::Config;
+use crate::importer::{self, ImporterClient, TriggerError};
+use crate::structs::{ImportRequest, ImportStatus};
+
+// Admin endpoints require "Authorization: Bearer <ADMIN_TOKEN>". Without a
+// configured token they don't exist as far as clients can tell.
+fn authorize(req: &HttpRequest, config: &Config) -> Option<HttpResponse> {
+    let Some(token) = &config.admin_token else {
+        return Some(HttpResponse::NotFound().finish());
+    };
+
+    let provided = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match provided {
+        Some(provided) if constant_time_eq(provided.as_bytes(), token.as_bytes()) => None,
+        _ => Some(
+            HttpResponse::Unauthorized()
+                .insert_header((WWW_AUTHENTICATE, "Bearer"))
+                .body("Invalid or missing admin token"),
+        ),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/rollback",
+    responses(
+        (status = 200, description = "Previous dataset restored", body = serde_json::Value),
+        (status = 401, description = "Invalid or missing admin token"),
+        (status = 404, description = "Admin API disabled"),
+        (status = 409, description = "No previous dataset to roll back to"),
+        (status = 500, description = "Rollback failed")
+    ),
+    tag = "Admin"
+)]
+pub async fn rollback(req: HttpRequest, config: web::Data<Config>, db: web::Data<PgPool>) -> Result<HttpResponse> {
+    if let Some(response) = authorize(&req, &config) {
+        return Ok(response);
+    }
+
+    match importer::rollback(db.as_ref()).await {
+        Ok(true) => {
+            info!("Rolled back to the previous dataset");
+            Ok(HttpResponse::Ok().json(serde_json::json!({ "rolled_back": true })))
+        }
+        Ok(false) => Ok(HttpResponse::Conflict().body("No previous dataset to roll back to")),
+        Err(e) => {
+            error!("Failed to roll back: {}", e);
+            Ok(HttpResponse::InternalServerError().body(format!("Rollback failed: {}", e)))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/import",
+    request_body(content = Option<ImportRequest>, description = "Optionally a different file to import"),
+    responses(
+        (status = 202, description = "Import queued; poll /admin/import/{id} for progress", body = serde_json::Value),
+        (status = 400, description = "Invalid request or file not found"),
+        (status = 401, description = "Invalid or missing admin token"),
+        (status = 404, description = "Admin API disabled"),
+        (status = 409, description = "An import is already running or queued"),
+        (status = 503, description = "The importer is disabled")
+    ),
+    tag = "Admin"
+)]
+pub async fn trigger_import(
+    req: HttpRequest,
+    body: web::Bytes,
+    config: web::Data<Config>,
+    importer: Option<web::Data<ImporterClient>>,
+) -> Result<HttpResponse> {
+    if let Some(response) = authorize(&req, &config) {
+        return Ok(response);
+    }
+    let Some(importer) = importer else {
+        return Ok(HttpResponse::ServiceUnavailable().body("The importer is disabled"));
+    };
+
+    let request = if body.is_empty() {
+        ImportRequest { path: None }
+    } else {
+        match serde_json::from_slice::<ImportRequest>(&body) {
+            Ok(request) => request,
+            Err(e) => return Ok(HttpResponse::BadRequest().body(format!("Invalid request body: {}", e))),
+        }
+    };
+
+    let path = PathBuf::from(request.path.as_deref().unwrap_or(&config.csv_path));
+    if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+        return Ok(HttpResponse::BadRequest().body(format!("File {} not found", path.display())));
+    }
+
+    match importer.trigger(path).await {
+        Ok(id) => Ok(HttpResponse::Accepted()
+            .insert_header((LOCATION, format!("/admin/import/{}", id)))
+            .json(serde_json::json!({ "id": id }))),
+        Err(TriggerError::Busy) => Ok(HttpResponse::Conflict().body("An import is already running or queued")),
+        Err(TriggerError::Database(e)) => {
+            error!("Failed to queue import: {}", e);
+            Ok(HttpResponse::InternalServerError().body(format!("Failed to queue import: {}", e)))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/import/{id}",
+    params(
+        ("id" = i64, Path, description = "Import run ID")
+    ),
+    responses(
+        (status = 200, description = "Import run, with progress while it is running", body = ImportStatus),
+        (status = 401, description = "Invalid or missing admin token"),
+        (status = 404, description = "Unknown import run, or admin API disabled")
+    ),
+    tag = "Admin"
+)]
+pub async fn import_status(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    config: web::Data<Config>,
+    importer: Option<web::Data<ImporterClient>>,
+    db: web::Data<PgPool>,
+) -> Result<HttpResponse> {
+    if let Some(response) = authorize(&req, &config) {
+        return Ok(response);
+    }
+
+    let id = path.into_inner();
+    match importer::find_run(db.as_ref(), id).await {
+        Ok(Some(run)) => {
+            let progress = importer
+                .and_then(|importer| importer.progress())
+                .filter(|progress| progress.run_id == Some(id));
+            Ok(HttpResponse::Ok().json(&ImportStatus { run, progress }))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().body("Unknown import run")),
+        Err(e) => Ok(HttpResponse::InternalServerError().body(format!("Failed to read import run: {}", e))),
+    }
+}
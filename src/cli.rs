@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use actix_web::web;
+use clap::{ArgGroup, Parser, Subcommand};
+use tracing::info;
+
+use crate::config::Config;
+use crate::database::{self, ReadPools};
+use crate::importer::{ImportOutcome, Importer};
+use crate::rate_limit::ClientLimits;
+use crate::routes;
+use crate::shutdown;
+use crate::upstream::Upstream;
+
+// Without a subcommand the mirror serves, as it always has.
+#[derive(Parser)]
+#[command(version, about = "A mirror of the SponsorBlock API")]
+pub struct Cli {
+    /// TOML config file; environment variables override its settings
+    #[arg(long, global = true, env = "CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the API server, along with the importer unless disabled
+    Serve {
+        /// Only serve; leave importing to the import command
+        #[arg(long)]
+        no_importer: bool,
+    },
+    /// Import a CSV dump once and exit
+    Import {
+        file: PathBuf,
+    },
+    /// Apply database migrations and exit
+    Migrate,
+    /// Look up segments and print the JSON response the API would give
+    #[command(group(ArgGroup::new("lookup").required(true).args(["video_id", "hash"])))]
+    Query {
+        #[arg(long)]
+        video_id: Option<String>,
+        /// 4-character hex prefix of the hashed video ID
+        #[arg(long)]
+        hash: Option<String>,
+        /// JSON array of categories, e.g. '["sponsor","intro"]'
+        #[arg(long)]
+        categories: Option<String>,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Print the effective configuration with secrets redacted, failing if
+    /// it is invalid
+    Check,
+}
+
+impl Cli {
+    pub fn command(&self) -> &Command {
+        const SERVE: Command = Command::Serve { no_importer: false };
+        self.command.as_ref().unwrap_or(&SERVE)
+    }
+
+    // Logs go to stderr when stdout carries the command's output.
+    pub fn logs_to_stderr(&self) -> bool {
+        matches!(self.command(), Command::Query { .. })
+    }
+}
+
+pub fn check_config(path: Option<&Path>) -> ExitCode {
+    match Config::load(path) {
+        Ok(config) => {
+            print!("{}", config.to_toml());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Exit codes of the import command, for cron jobs and Kubernetes jobs to
+// tell a rejected dump (old data kept on purpose) from a failure.
+const EXIT_REJECTED: u8 = 2;
+
+pub async fn import(config: &Config, file: &Path) -> io::Result<ExitCode> {
+    let pool = database::connect_for_import(config).await;
+    database::run_migrations(config, &pool).await;
+
+    // Roll back on Ctrl-C or SIGTERM, so the run is recorded as interrupted
+    // rather than left running
+    let importer = Importer::new(pool.clone(), config);
+    let interrupt = importer.interrupt_token();
+    tokio::spawn(async move {
+        shutdown::requested().await;
+        interrupt.cancel();
+    });
+
+    let outcome = importer.import_file(file).await;
+    pool.close().await;
+
+    match outcome {
+        ImportOutcome::Imported => Ok(ExitCode::SUCCESS),
+        ImportOutcome::Rejected => Ok(ExitCode::from(EXIT_REJECTED)),
+        ImportOutcome::Failed => Ok(ExitCode::FAILURE),
+    }
+}
+
+pub async fn migrate(config: &Config) -> io::Result<ExitCode> {
+    let pool = database::connect_for_import(config).await;
+    database::run_migrations(config, &pool).await;
+    info!("Migrations applied");
+    Ok(ExitCode::SUCCESS)
+}
+
+// Runs the lookup through the same handlers as the API, so the output
+// matches what a client would get, upstream fallback included.
+pub async fn query(
+    config: &Config,
+    video_id: Option<&str>,
+    hash: Option<&str>,
+    categories: Option<&str>,
+) -> io::Result<ExitCode> {
+    // Replicas only come into rotation once the server has checked them, so
+    // this reads from the primary.
+    let pool = database::connect(config).await;
+    let db = web::Data::new(ReadPools::new(config, pool));
+    let upstream = Upstream::new(config).map(web::Data::new);
+    let config = web::Data::new(config.clone());
+
+    let mut params = HashMap::new();
+    if let Some(categories) = categories {
+        params.insert("categories".to_string(), categories.to_string());
+    }
+
+    let response = match (video_id, hash) {
+        (Some(video_id), _) => {
+            params.insert("videoID".to_string(), video_id.to_string());
+            routes::skip_segments_by_id(web::Query(params), db, config, upstream, ClientLimits::unlimited()).await
+        }
+        (None, Some(hash)) => {
+            routes::skip_segments(web::Path::from(hash.to_string()), web::Query(params), db, config, upstream, ClientLimits::unlimited()).await
+        }
+        (None, None) => unreachable!("clap requires --video-id or --hash"),
+    }
+    .map_err(|e| io::Error::other(e.to_string()))?;
+
+    let status = response.status();
+    let body = actix_web::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    let body = String::from_utf8_lossy(&body);
+
+    if status.is_success() {
+        println!("{}", body);
+        Ok(ExitCode::SUCCESS)
+    } else {
+        eprintln!("{}: {}", status, body);
+        Ok(ExitCode::FAILURE)
+    }
+}
@@ -0,0 +1,271 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use sqlx::migrate::MigrateError;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{Connection, PgConnection, PgPool};
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+use crate::config::{redact_url, Config};
+
+// The importer works from a single task: one connection holds the import
+// transaction, the others record runs and serve the admin API's requests.
+const IMPORT_POOL_SIZE: u32 = 3;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+// Pool for API queries. Its connections are subject to the statement
+// timeout, so a slow query can't hold on to one indefinitely.
+pub async fn connect(config: &Config) -> PgPool {
+    open(config, api_pool_options(config), api_connect_options(config, &config.database_url)).await
+}
+
+// Pool for imports and migrations. COPY, index builds and VACUUM run for
+// minutes on the full dataset, so these connections have no statement
+// timeout.
+pub async fn connect_for_import(config: &Config) -> PgPool {
+    let options = connect_options(&config.database_url);
+    let pool = PgPoolOptions::new()
+        .max_connections(IMPORT_POOL_SIZE)
+        .acquire_timeout(config.database_acquire_timeout());
+
+    open(config, pool, options).await
+}
+
+// Wait for the database with single connections, which fail right away while
+// it is down, rather than with the pool, which keeps trying until the acquire
+// timeout.
+async fn open(config: &Config, pool: PgPoolOptions, options: PgConnectOptions) -> PgPool {
+    let probe = retry(config, "connect to the database", is_transient, || PgConnection::connect_with(&options))
+        .await
+        .expect("Failed to connect to the database");
+    let _ = probe.close().await;
+
+    pool.connect_with(options).await.expect("Failed to create database pool")
+}
+
+pub async fn run_migrations(config: &Config, pool: &PgPool) {
+    let is_transient_migration = |e: &MigrateError| match e {
+        MigrateError::Execute(e) | MigrateError::ExecuteMigration(e, _) => is_transient(e),
+        _ => false,
+    };
+
+    retry(config, "run migrations", is_transient_migration, || sqlx::migrate!("./migrations").run(pool))
+        .await
+        .expect("Failed to run migrations");
+}
+
+fn connect_options(url: &str) -> PgConnectOptions {
+    url.parse()
+        .unwrap_or_else(|_| panic!("{} is not a valid connection string", redact_url(url)))
+}
+
+fn api_connect_options(config: &Config, url: &str) -> PgConnectOptions {
+    let options = connect_options(url);
+    if config.database_statement_timeout_seconds == 0 {
+        return options;
+    }
+    options.options([("statement_timeout", format!("{}s", config.database_statement_timeout_seconds))])
+}
+
+fn api_pool_options(config: &Config) -> PgPoolOptions {
+    PgPoolOptions::new()
+        .max_connections(config.database_max_connections)
+        .min_connections(config.database_min_connections)
+        .acquire_timeout(config.database_acquire_timeout())
+}
+
+// Where segment lookups read from: the replicas in DATABASE_REPLICA_URLS in
+// turn, so imports on the primary don't slow the API down, and the primary
+// when none of them is healthy. Without replicas everything reads from the
+// primary.
+#[derive(Clone)]
+pub struct ReadPools {
+    primary: PgPool,
+    replicas: Arc<Vec<Replica>>,
+    next: Arc<AtomicUsize>,
+}
+
+struct Replica {
+    // Redacted, for logs and the health check
+    url: String,
+    pool: PgPool,
+    healthy: AtomicBool,
+}
+
+impl Replica {
+    fn set_healthy(&self, healthy: bool, reason: &dyn Display) {
+        if self.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+            if healthy {
+                info!("Replica {} is healthy, reading from it", self.url);
+            } else {
+                warn!("Replica {} is unhealthy, reading from the primary instead: {}", self.url, reason);
+            }
+        }
+    }
+}
+
+pub struct ReplicaStatus {
+    pub healthy: bool,
+}
+
+impl ReadPools {
+    // Replicas are connected lazily and start out unhealthy, so one that is
+    // down doesn't hold up startup; the first health check brings them in.
+    pub fn new(config: &Config, primary: PgPool) -> Self {
+        let replicas = config
+            .database_replica_urls
+            .iter()
+            .map(|url| Replica {
+                url: redact_url(url),
+                pool: api_pool_options(config).connect_lazy_with(api_connect_options(config, url)),
+                healthy: AtomicBool::new(false),
+            })
+            .collect();
+
+        ReadPools {
+            primary,
+            replicas: Arc::new(replicas),
+            next: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn primary(&self) -> &PgPool {
+        &self.primary
+    }
+
+    // Close the primary and the replicas, waiting for connections in use to
+    // be returned.
+    pub async fn close(&self) {
+        self.primary.close().await;
+        for replica in self.replicas.iter() {
+            replica.pool.close().await;
+        }
+    }
+
+    pub fn replica_status(&self) -> Vec<ReplicaStatus> {
+        self.replicas
+            .iter()
+            .map(|replica| ReplicaStatus {
+                healthy: replica.healthy.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    // Run a read on the next healthy replica. If the replica looks
+    // unreachable, it is taken out of rotation and the read is tried again on
+    // the primary. Other errors, like statement timeouts, would most likely
+    // happen on the primary too, so they are returned as they are.
+    pub async fn read<T, F, Fut>(&self, query: F) -> Result<T, sqlx::Error>
+    where
+        F: Fn(PgPool) -> Fut,
+        Fut: Future<Output = Result<T, sqlx::Error>>,
+    {
+        let Some(replica) = self.pick() else {
+            return query(self.primary.clone()).await;
+        };
+
+        match query(replica.pool.clone()).await {
+            Ok(value) => Ok(value),
+            Err(e) if is_unavailable(&e) => {
+                replica.set_healthy(false, &e);
+                query(self.primary.clone()).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn pick(&self) -> Option<&Replica> {
+        let count = self.replicas.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..count)
+            .map(|offset| &self.replicas[(start + offset) % count])
+            .find(|replica| replica.healthy.load(Ordering::Relaxed))
+    }
+
+    // Check every replica each DATABASE_REPLICA_CHECK_INTERVAL_SECONDS, for
+    // as long as the server runs.
+    pub fn spawn_health_checks(&self, config: &Config) {
+        if self.replicas.is_empty() {
+            return;
+        }
+
+        let replicas = self.replicas.clone();
+        let mut ticker = interval(config.database_replica_check_interval());
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        tokio::spawn(async move {
+            loop {
+                ticker.tick().await;
+                for replica in replicas.iter() {
+                    if replica.pool.is_closed() {
+                        return;
+                    }
+                    // Reads the table lookups use, so a replica that can't
+                    // serve them, e.g. as it can't read an unlogged table,
+                    // is taken out of rotation too
+                    match sqlx::query(r#"SELECT 1 FROM "sponsorTimes" LIMIT 1"#).execute(&replica.pool).await {
+                        Ok(_) => replica.set_healthy(true, &"check succeeded"),
+                        Err(e) => replica.set_healthy(false, &e),
+                    }
+                }
+            }
+        });
+    }
+}
+
+// Errors that mean the server can't be reached, as opposed to a query that
+// failed on it.
+fn is_unavailable(e: &sqlx::Error) -> bool {
+    matches!(
+        e,
+        sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed
+    ) || is_transient(e)
+}
+
+// Errors worth waiting out while the database starts up: it isn't reachable
+// yet, or it is up but not accepting connections.
+fn is_transient(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => true,
+        // cannot_connect_now (starting up or shutting down), too_many_connections
+        sqlx::Error::Database(e) => matches!(e.code().as_deref(), Some("57P03" | "53300")),
+        _ => false,
+    }
+}
+
+// Retry with exponential backoff, up to DATABASE_CONNECT_RETRIES times. The
+// database often comes up a few seconds after the mirror in docker-compose.
+async fn retry<T, E, F, Fut>(config: &Config, action: &str, transient: impl Fn(&E) -> bool, mut attempt: F) -> Result<T, E>
+where
+    E: Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut delay = INITIAL_BACKOFF;
+    let mut retries = 0;
+
+    loop {
+        match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(e) if retries < config.database_connect_retries && transient(&e) => {
+                retries += 1;
+                warn!(
+                    "Failed to {} (attempt {} of {}), retrying in {}s: {}",
+                    action,
+                    retries,
+                    config.database_connect_retries + 1,
+                    delay.as_secs(),
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(config.database_connect_max_backoff());
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use reqwest::header::{HeaderMap, CONTENT_RANGE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::config::Config;
+use crate::importer::{ImporterClient, TriggerError};
+
+// Fetches the CSV dump over HTTP into CSV_PATH, so a mirror can run without
+// a separate rsync container. Unchanged dumps are skipped with conditional
+// requests, interrupted downloads are resumed with range requests, and the
+// finished file is renamed into place so the importer never sees it half
+// written. The importer is then asked to import it straight away.
+pub struct Downloader {
+    client: reqwest::Client,
+    url: String,
+    importer: ImporterClient,
+    dest: PathBuf,
+    interval: Duration,
+    shutdown: CancellationToken,
+}
+
+pub struct DownloaderHandle {
+    shutdown: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl DownloaderHandle {
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+
+    pub async fn join(self) {
+        if let Err(e) = self.task.await {
+            error!("Downloader task failed: {}", e);
+        }
+    }
+}
+
+#[derive(Debug)]
+enum DownloadError {
+    Http(reqwest::Error),
+    Io(std::io::Error),
+    Status(StatusCode),
+    // The server won't continue the partial download where it left off
+    ResumeRejected(String),
+    Cancelled,
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::Http(e) => write!(f, "HTTP error: {}", e),
+            DownloadError::Io(e) => write!(f, "I/O error: {}", e),
+            DownloadError::Status(status) => write!(f, "unexpected response status {}", status),
+            DownloadError::ResumeRejected(reason) => write!(f, "cannot resume: {}", reason),
+            DownloadError::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl From<reqwest::Error> for DownloadError {
+    fn from(e: reqwest::Error) -> Self {
+        DownloadError::Http(e)
+    }
+}
+
+impl From<std::io::Error> for DownloadError {
+    fn from(e: std::io::Error) -> Self {
+        DownloadError::Io(e)
+    }
+}
+
+// Cache validators of a downloaded file, kept in a sidecar file so they
+// survive restarts.
+#[derive(Serialize, Deserialize, Default)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let get = |name| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+        Validators {
+            etag: get(ETAG),
+            last_modified: get(LAST_MODIFIED),
+        }
+    }
+
+    async fn load(path: &Path) -> Self {
+        match tokio::fs::read(path).await {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_default(),
+            Err(_) => Validators::default(),
+        }
+    }
+
+    async fn save(&self, path: &Path) -> std::io::Result<()> {
+        tokio::fs::write(path, serde_json::to_vec(self)?).await
+    }
+}
+
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+enum Outcome {
+    NotModified,
+    Downloaded(u64),
+}
+
+impl Downloader {
+    pub fn new(url: String, importer: ImporterClient, config: &Config) -> Self {
+        // Transfer encodings are disabled: range requests on a compressed
+        // representation can't be resumed into the decoded file.
+        let client = reqwest::Client::builder()
+            .no_gzip()
+            .no_brotli()
+            .connect_timeout(Duration::from_secs(30))
+            // Between reads, so that a stalled transfer fails instead of
+            // hanging until shutdown, however long the whole dump takes
+            .read_timeout(READ_TIMEOUT)
+            .build()
+            .expect("Failed to build HTTP client");
+
+        Downloader {
+            client,
+            url,
+            importer,
+            dest: PathBuf::from(&config.csv_path),
+            interval: config.download_interval(),
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    pub fn spawn(self) -> DownloaderHandle {
+        let shutdown = self.shutdown.clone();
+        let task = tokio::spawn(self.run());
+        DownloaderHandle { shutdown, task }
+    }
+
+    async fn run(self) {
+        let mut interval = interval(self.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+
+            let start = Instant::now();
+            match self.download().await {
+                Ok(Outcome::NotModified) => debug!("Dump at {} not modified", self.url),
+                Ok(Outcome::Downloaded(bytes)) => {
+                    info!("Downloaded {} bytes from {} in {}ms", bytes, self.url, start.elapsed().as_millis());
+                    self.hand_over().await;
+                }
+                Err(DownloadError::Cancelled) => break,
+                Err(e) => error!("Failed to download {}: {}", self.url, e),
+            }
+        }
+
+        info!("Downloader stopped");
+    }
+
+    // A finished dump appears in one rename, so it is imported without the
+    // stability checks the importer makes for files written by others. While
+    // an import is running the importer notices the new file itself.
+    async fn hand_over(&self) {
+        match self.importer.trigger(self.dest.clone()).await {
+            Ok(run_id) => info!("Queued import run {} of {}", run_id, self.dest.display()),
+            Err(TriggerError::Busy) => debug!("Importer busy, leaving {} to be picked up", self.dest.display()),
+            Err(TriggerError::Database(e)) => warn!("Failed to queue import of {}: {}", self.dest.display(), e),
+        }
+    }
+
+    // Partial downloads are kept next to the destination, named outside
+    // rsync's ".sponsorTimes.csv.*" temporary files so they don't hold imports
+    // of the dump already there off.
+    fn sidecar(&self, suffix: &str) -> PathBuf {
+        let name = self.dest.file_name().unwrap_or_default().to_string_lossy();
+        self.dest.with_file_name(format!("{}.{}", name, suffix))
+    }
+
+    // A partial download the server can't resume is thrown away and fetched
+    // again from the start, rather than failing the same way every cycle.
+    async fn download(&self) -> Result<Outcome, DownloadError> {
+        match self.fetch().await {
+            Err(DownloadError::ResumeRejected(reason)) => {
+                warn!("Cannot resume download of {}: {}, starting over", self.url, reason);
+                let _ = tokio::fs::remove_file(self.sidecar("part")).await;
+                let _ = tokio::fs::remove_file(self.sidecar("part.meta")).await;
+                self.fetch().await
+            }
+            outcome => outcome,
+        }
+    }
+
+    async fn fetch(&self) -> Result<Outcome, DownloadError> {
+        let part_path = self.sidecar("part");
+        let part_meta_path = self.sidecar("part.meta");
+        let meta_path = self.sidecar("meta");
+
+        let mut request = self.client.get(&self.url);
+
+        let partial_len = tokio::fs::metadata(&part_path).await.map(|m| m.len()).unwrap_or(0);
+        let partial = Validators::load(&part_meta_path).await;
+        let resume_from = match partial.etag.as_ref().or(partial.last_modified.as_ref()) {
+            Some(validator) if partial_len > 0 => {
+                request = request
+                    .header(RANGE, format!("bytes={}-", partial_len))
+                    .header(IF_RANGE, validator);
+                Some(partial_len)
+            }
+            _ => None,
+        };
+
+        // Conditional request against the completed file, as long as it is
+        // still there.
+        if resume_from.is_none() && tokio::fs::try_exists(&self.dest).await.unwrap_or(false) {
+            let current = Validators::load(&meta_path).await;
+            if let Some(etag) = &current.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &current.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let mut response = request.send().await?;
+        let validators = Validators::from_headers(response.headers());
+
+        let mut file = match response.status() {
+            StatusCode::NOT_MODIFIED => return Ok(Outcome::NotModified),
+            StatusCode::PARTIAL_CONTENT if resume_from.is_some() => {
+                match content_range_start(response.headers()) {
+                    Some(start) if start == partial_len => {}
+                    Some(start) => {
+                        return Err(DownloadError::ResumeRejected(format!(
+                            "asked for byte {} but got a range starting at byte {}",
+                            partial_len, start
+                        )));
+                    }
+                    None => return Err(DownloadError::ResumeRejected("invalid Content-Range".to_string())),
+                }
+                info!("Resuming download of {} at byte {}", self.url, partial_len);
+                tokio::fs::OpenOptions::new().append(true).open(&part_path).await?
+            }
+            StatusCode::OK => {
+                if resume_from.is_some() {
+                    warn!("Dump at {} changed since the partial download, starting over", self.url);
+                }
+                validators.save(&part_meta_path).await?;
+                tokio::fs::File::create(&part_path).await?
+            }
+            // Typically a partial download that is already complete but was
+            // never renamed into place
+            StatusCode::RANGE_NOT_SATISFIABLE if resume_from.is_some() => {
+                return Err(DownloadError::ResumeRejected(format!(
+                    "range from byte {} not satisfiable",
+                    partial_len
+                )));
+            }
+            status => return Err(DownloadError::Status(status)),
+        };
+
+        let mut written = 0;
+        loop {
+            let chunk = tokio::select! {
+                _ = self.shutdown.cancelled() => {
+                    file.flush().await?;
+                    info!("Download of {} interrupted, will resume on next start", self.url);
+                    return Err(DownloadError::Cancelled);
+                }
+                chunk = response.chunk() => chunk?,
+            };
+            let Some(chunk) = chunk else { break };
+            file.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+
+        file.sync_all().await?;
+        drop(file);
+
+        tokio::fs::rename(&part_path, &self.dest).await?;
+        // Validators of a resumed download are those of the original
+        // response; a 206 doesn't necessarily repeat them.
+        let validators = if resume_from.is_some() {
+            Validators::load(&part_meta_path).await
+        } else {
+            validators
+        };
+        validators.save(&meta_path).await?;
+        let _ = tokio::fs::remove_file(&part_meta_path).await;
+
+        Ok(Outcome::Downloaded(written))
+    }
+}
+
+// The first byte of a 206 response, from a Content-Range such as
+// "bytes 1000-1999/2000".
+fn content_range_start(headers: &HeaderMap) -> Option<u64> {
+    let range = headers.get(CONTENT_RANGE)?.to_str().ok()?;
+    let (start, _) = range.strip_prefix("bytes ")?.split_once('-')?;
+    start.trim().parse().ok()
+}
@@ -0,0 +1,116 @@
+use std::fmt;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use tokio::fs::File;
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt, BufReader, ReadBuf};
+
+// Mirrors may publish the dump compressed. The format is taken from the file
+// extension, or sniffed from the magic bytes when the extension says nothing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+impl Compression {
+    fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" | "gzip" => Some(Compression::Gzip),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            "csv" => Some(Compression::None),
+            _ => None,
+        }
+    }
+
+    fn from_magic(magic: &[u8]) -> Self {
+        if magic.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if magic.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    pub async fn detect(path: &Path) -> std::io::Result<Self> {
+        if let Some(compression) = Compression::from_extension(path) {
+            return Ok(compression);
+        }
+
+        let mut magic = [0u8; 4];
+        let mut file = File::open(path).await?;
+        let mut read = 0;
+        while read < magic.len() {
+            match file.read(&mut magic[read..]).await? {
+                0 => break,
+                n => read += n,
+            }
+        }
+
+        Ok(Compression::from_magic(&magic[..read]))
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "uncompressed"),
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+pub type DumpReader = Pin<Box<dyn AsyncBufRead + Send>>;
+
+// Counts the bytes read from the file itself, before decompression, so
+// progress can be compared against the size of the file.
+struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+        self.count.fetch_add(read as u64, Ordering::Relaxed);
+        result
+    }
+}
+
+// Open the dump for reading, decompressing on the fly. The number of bytes
+// read from disk so far is kept in `read_bytes`.
+pub async fn open(path: &Path, read_bytes: Arc<AtomicU64>) -> std::io::Result<(Compression, DumpReader)> {
+    let compression = Compression::detect(path).await?;
+    let file = BufReader::new(CountingReader {
+        inner: File::open(path).await?,
+        count: read_bytes,
+    });
+
+    let reader: DumpReader = match compression {
+        Compression::None => Box::pin(file),
+        Compression::Gzip => {
+            let mut decoder = GzipDecoder::new(file);
+            decoder.multiple_members(true);
+            Box::pin(BufReader::new(decoder))
+        }
+        Compression::Zstd => {
+            let mut decoder = ZstdDecoder::new(file);
+            decoder.multiple_members(true);
+            Box::pin(BufReader::new(decoder))
+        }
+    };
+
+    Ok((compression, reader))
+}
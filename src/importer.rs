@@ -0,0 +1,1700 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgPool};
+use tokio::io::AsyncBufReadExt;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{self, interval, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, info_span, instrument, warn, Span};
+
+use crate::config::{Config, ImportMode, WatchMode};
+use crate::dump::{self, DumpReader};
+use crate::metrics;
+use crate::models::ImportRun;
+use crate::structs::ImportProgress;
+use crate::watcher::FileWatcher;
+
+// Keeps the "sponsorTimes" table in sync with the CSV dump on disk. The
+// importer runs as its own task and never blocks a runtime worker: waits are
+// async and can be interrupted through the cancellation token. Imports can
+// also be requested on demand through an ImporterClient.
+//
+// Shutting down stops the importer from starting new imports; a running one
+// is only interrupted, and rolled back, through the separate interrupt token.
+pub struct Importer {
+    pool: PgPool,
+    csv_path: PathBuf,
+    check_interval: Duration,
+    file_check_interval: Duration,
+    watch_mode: WatchMode,
+    watch_debounce: Duration,
+    rsync_stale: Duration,
+    lock_path: Option<PathBuf>,
+    last_handled: Option<SourceFile>,
+    pending: Option<SourceFile>,
+    last_checked: Option<Instant>,
+    options: ImportOptions,
+    progress: Arc<ProgressTracker>,
+    commands: mpsc::Receiver<ImportCommand>,
+    client: ImporterClient,
+    shutdown: CancellationToken,
+    interrupt: CancellationToken,
+}
+
+pub struct ImporterHandle {
+    client: ImporterClient,
+    shutdown: CancellationToken,
+    interrupt: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl ImporterHandle {
+    pub fn client(&self) -> ImporterClient {
+        self.client.clone()
+    }
+
+    // Stop the importer, giving a running import until the deadline to
+    // finish before interrupting it.
+    pub async fn stop(mut self, deadline: Duration) {
+        self.shutdown.cancel();
+        if let Some(progress) = self.client.progress() {
+            info!("Waiting up to {}s for the running import ({}) to finish", deadline.as_secs(), progress.stage);
+        }
+
+        let result = match time::timeout(deadline, &mut self.task).await {
+            Ok(result) => result,
+            Err(_) => {
+                if let Some(progress) = self.client.progress() {
+                    warn!(
+                        "Import still {} after {}s ({} of {} bytes read), interrupting it",
+                        progress.stage,
+                        deadline.as_secs(),
+                        progress.bytes_read,
+                        progress.bytes_total
+                    );
+                }
+                self.interrupt.cancel();
+                self.task.await
+            }
+        };
+
+        if let Err(e) = result {
+            error!("Importer task failed: {}", e);
+        }
+    }
+}
+
+// An import requested through the admin API. The import run is recorded
+// before the command is queued, so the caller has an ID to poll right away.
+struct ImportCommand {
+    run_id: i64,
+    path: PathBuf,
+}
+
+#[derive(Debug)]
+pub enum TriggerError {
+    Busy,
+    Database(sqlx::Error),
+}
+
+#[derive(Clone)]
+pub struct ImporterClient {
+    pool: PgPool,
+    commands: mpsc::Sender<ImportCommand>,
+    progress: Arc<ProgressTracker>,
+}
+
+impl ImporterClient {
+    // Queue an import of the given file, returning the ID of its import run.
+    // Only one import can be waiting at a time.
+    pub async fn trigger(&self, path: PathBuf) -> Result<i64, TriggerError> {
+        if self.progress.snapshot().is_some() {
+            return Err(TriggerError::Busy);
+        }
+        let permit = self.commands.try_reserve().map_err(|_| TriggerError::Busy)?;
+
+        let run_id = sqlx::query_scalar(
+            r#"INSERT INTO import_runs (status, source_path) VALUES ('queued', $1) RETURNING id"#,
+        )
+        .bind(path.display().to_string())
+        .fetch_one(&self.pool)
+        .await
+        .map_err(TriggerError::Database)?;
+
+        permit.send(ImportCommand { run_id, path });
+
+        Ok(run_id)
+    }
+
+    // Progress of the import currently running, if any.
+    pub fn progress(&self) -> Option<ImportProgress> {
+        self.progress.snapshot()
+    }
+}
+
+// Stages of an import, as reported through the admin API. Failures are
+// counted by the coarser step they belong to.
+#[derive(Clone, Copy)]
+enum Stage {
+    Starting,
+    Loading,
+    Validating,
+    Indexing,
+    Swapping,
+    CopyingPrevious,
+    ApplyingChanges,
+    Vacuuming,
+}
+
+impl Stage {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Starting => "starting",
+            Stage::Loading => "loading",
+            Stage::Validating => "validating",
+            Stage::Indexing => "indexing",
+            Stage::Swapping => "swapping",
+            Stage::CopyingPrevious => "copying previous generation",
+            Stage::ApplyingChanges => "applying changes",
+            Stage::Vacuuming => "vacuuming",
+        }
+    }
+
+    fn step(&self) -> &'static str {
+        match self {
+            Stage::Starting | Stage::Loading | Stage::Validating => "copy",
+            Stage::Indexing | Stage::Swapping | Stage::CopyingPrevious | Stage::ApplyingChanges => "swap",
+            Stage::Vacuuming => "vacuum",
+        }
+    }
+}
+
+struct Running {
+    run_id: Option<i64>,
+    stage: Stage,
+    bytes_total: u64,
+}
+
+// Tracks the stage of the running import and how much of the dump has been
+// read, for reporting through the admin API. Each stage also gets a span,
+// which lasts until the next stage starts, for trace export.
+#[derive(Default)]
+struct ProgressTracker {
+    current: Mutex<Option<Running>>,
+    bytes_read: Arc<AtomicU64>,
+    stage_span: Mutex<Option<Span>>,
+}
+
+impl ProgressTracker {
+    fn start(&self, bytes_total: u64) {
+        self.bytes_read.store(0, Ordering::Relaxed);
+        *self.current.lock().unwrap() = Some(Running {
+            run_id: None,
+            stage: Stage::Starting,
+            bytes_total,
+        });
+        self.start_stage_span(Stage::Starting);
+    }
+
+    fn set_run_id(&self, run_id: i64) {
+        if let Some(current) = self.current.lock().unwrap().as_mut() {
+            current.run_id = Some(run_id);
+        }
+    }
+
+    fn stage(&self, stage: Stage) {
+        if let Some(current) = self.current.lock().unwrap().as_mut() {
+            current.stage = stage;
+        }
+        self.start_stage_span(stage);
+    }
+
+    // Closes the previous stage's span. The new one is a child of the span
+    // of the import, which is current while the import runs.
+    fn start_stage_span(&self, stage: Stage) {
+        *self.stage_span.lock().unwrap() = Some(info_span!("import_stage", stage = stage.as_str()));
+    }
+
+    fn current_stage(&self) -> Option<Stage> {
+        self.current.lock().unwrap().as_ref().map(|current| current.stage)
+    }
+
+    fn finish(&self) {
+        *self.current.lock().unwrap() = None;
+        *self.stage_span.lock().unwrap() = None;
+    }
+
+    fn snapshot(&self) -> Option<ImportProgress> {
+        let current = self.current.lock().unwrap();
+        let current = current.as_ref()?;
+        Some(ImportProgress {
+            run_id: current.run_id,
+            stage: current.stage.as_str().to_string(),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_total: current.bytes_total,
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ImportOptions {
+    mode: ImportMode,
+    checks: ImportChecks,
+    keep_generations: u32,
+}
+
+impl Importer {
+    pub fn new(pool: PgPool, config: &Config) -> Self {
+        let (sender, commands) = mpsc::channel(1);
+        let progress = Arc::new(ProgressTracker::default());
+        let client = ImporterClient {
+            pool: pool.clone(),
+            commands: sender,
+            progress: progress.clone(),
+        };
+
+        Importer {
+            pool,
+            csv_path: PathBuf::from(&config.csv_path),
+            check_interval: config.check_interval(),
+            file_check_interval: config.file_check_interval(),
+            watch_mode: config.watch_mode,
+            watch_debounce: config.watch_debounce(),
+            rsync_stale: config.rsync_stale(),
+            lock_path: config.csv_lock_path.as_ref().map(PathBuf::from),
+            last_handled: None,
+            pending: None,
+            last_checked: None,
+            options: ImportOptions {
+                mode: config.import_mode,
+                checks: ImportChecks {
+                    min_rows: config.import_min_rows,
+                    max_shrink_ratio: config.import_max_shrink_ratio,
+                },
+                keep_generations: config.import_keep_generations,
+            },
+            progress,
+            commands,
+            client,
+            shutdown: CancellationToken::new(),
+            interrupt: CancellationToken::new(),
+        }
+    }
+
+    pub fn spawn(self) -> ImporterHandle {
+        let shutdown = self.shutdown.clone();
+        let interrupt = self.interrupt.clone();
+        let client = self.client.clone();
+        let task = tokio::spawn(self.run());
+        ImporterHandle { client, shutdown, interrupt, task }
+    }
+
+    // Cancelling the token interrupts the running import, which is rolled
+    // back and recorded as interrupted.
+    pub fn interrupt_token(&self) -> CancellationToken {
+        self.interrupt.clone()
+    }
+
+    async fn run(mut self) {
+        match fail_abandoned_runs(&self.pool).await {
+            Ok(0) => {}
+            Ok(count) => warn!("Marked {} import runs left unfinished by an earlier process as failed", count),
+            Err(e) => error!("Failed to clean up unfinished import runs: {}", e),
+        }
+        self.restore_metrics().await;
+        self.skip_rolled_back().await;
+        self.check_generations().await;
+
+        match self.watcher() {
+            Some(watcher) => self.watch(watcher).await,
+            None => self.poll().await,
+        }
+
+        info!("Importer stopped");
+    }
+
+    // Pick up where the last run left off, so the gauges don't read as if
+    // nothing had ever been imported until the next dump arrives.
+    async fn restore_metrics(&self) {
+        match last_successful_run(&self.pool).await {
+            Ok(Some(run)) => {
+                let finished = run.finished_at.unwrap_or(run.started_at);
+                metrics::get().import_last_success.set(finished.timestamp_millis() as f64 / 1000.0);
+            }
+            Ok(None) => {}
+            Err(e) => error!("Failed to read last import run: {}", e),
+        }
+
+        // After a rollback the data being served is that of an earlier import
+        match current_dataset_run(&self.pool).await {
+            Ok(Some(run)) => set_dataset_metrics(&run),
+            Ok(None) => {}
+            Err(e) => error!("Failed to read the current dataset: {}", e),
+        }
+
+        update_category_metrics(&self.pool).await;
+    }
+
+    // A rolled back dump stays on disk until a new one replaces it. Without
+    // this, a restart would find it unhandled and import it again.
+    async fn skip_rolled_back(&mut self) {
+        match rolled_back_source(&self.pool).await {
+            Ok(Some(source)) => {
+                info!("Skipping {} until it changes, its import was rolled back", source.path.display());
+                self.last_handled = Some(source);
+            }
+            Ok(None) => {}
+            Err(e) => error!("Failed to read the current dataset: {}", e),
+        }
+    }
+
+    // Lowering IMPORT_KEEP_GENERATIONS, which switching to incremental mode
+    // does unless it is set, drops the datasets beyond it on the next import.
+    async fn check_generations(&self) {
+        let generations = match self.pool.acquire().await {
+            Ok(mut conn) => previous_generations(&mut conn).await,
+            Err(e) => Err(e),
+        };
+        match generations {
+            Ok(generations) if generations.len() > self.options.keep_generations as usize => warn!(
+                "Found {} previous datasets, more than IMPORT_KEEP_GENERATIONS ({}); the next import will drop {}",
+                generations.len(),
+                self.options.keep_generations,
+                generations.len() - self.options.keep_generations as usize
+            ),
+            Ok(_) => {}
+            Err(e) => error!("Failed to read previous datasets: {}", e),
+        }
+    }
+
+    fn watcher(&self) -> Option<FileWatcher> {
+        if self.watch_mode == WatchMode::Poll {
+            return None;
+        }
+
+        match FileWatcher::new(&self.csv_path, self.lock_path.as_deref()) {
+            Ok(watcher) => {
+                info!("Watching {} for changes", self.csv_path.display());
+                Some(watcher)
+            }
+            Err(e) => {
+                warn!(
+                    "File notifications unavailable for {}, polling instead: {}",
+                    self.csv_path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    // Look at the file once things have been quiet for the debounce period:
+    // rsync touches its temporary file many times before renaming it into
+    // place. The first look happens right away, as the file may have changed
+    // while the mirror wasn't running.
+    async fn watch(&mut self, watcher: FileWatcher) {
+        let mut deadline = Some(time::Instant::now());
+
+        loop {
+            let due = async {
+                match deadline {
+                    Some(deadline) => time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                Some(command) = self.commands.recv() => {
+                    self.handle_command(command).await;
+                    continue;
+                }
+                _ = watcher.changed() => {
+                    deadline = Some(time::Instant::now() + self.watch_debounce);
+                    continue;
+                }
+                _ = due => {}
+            }
+
+            // Without further notifications, a file waiting to settle is
+            // looked at again after another quiet period, and a failed import
+            // is retried after the file check interval.
+            deadline = match self.check_file().await {
+                Recheck::Settling => Some(time::Instant::now() + self.watch_debounce),
+                Recheck::Retry => Some(time::Instant::now() + self.file_check_interval),
+                Recheck::OnChange => None,
+            };
+        }
+    }
+
+    async fn poll(&mut self) {
+        let mut interval = interval(self.check_interval);
+        // An import can take several minutes; don't fire a burst of ticks
+        // to catch up afterwards.
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                Some(command) = self.commands.recv() => {
+                    self.handle_command(command).await;
+                    continue;
+                }
+                _ = interval.tick() => {}
+            }
+
+            if self.file_check_due() {
+                self.last_checked = Some(Instant::now());
+                self.check_file().await;
+            }
+        }
+    }
+
+    // A file waiting to settle is looked at again on the next tick rather than
+    // after the full file check interval.
+    fn file_check_due(&self) -> bool {
+        if self.pending.is_some() {
+            return true;
+        }
+
+        match self.last_checked {
+            Some(checked) => checked.elapsed() >= self.file_check_interval,
+            None => true,
+        }
+    }
+
+    // On-demand imports skip the stability checks: whoever asked for the
+    // import knows the file is ready.
+    async fn handle_command(&mut self, command: ImportCommand) {
+        info!("Import of {} requested", command.path.display());
+
+        let source = match SourceFile::read(&command.path).await {
+            Ok(source) => source,
+            Err(e) => {
+                error!("Failed to read {}: {}", command.path.display(), e);
+                let result = Err(ImportError::Io(e));
+                if let Err(e) = finish_run(&self.pool, command.run_id, Duration::ZERO, &result).await {
+                    error!("Failed to record import run: {}", e);
+                }
+                return;
+            }
+        };
+
+        if self.run_import(&source, Some(command.run_id)).await.handled() && source.path == self.csv_path {
+            self.last_handled = Some(source);
+            self.pending = None;
+        }
+    }
+
+    async fn check_file(&mut self) -> Recheck {
+        let source = match SourceFile::read(&self.csv_path).await {
+            Ok(source) => source,
+            Err(e) => {
+                debug!("CSV file {} not available: {}", self.csv_path.display(), e);
+                return Recheck::OnChange;
+            }
+        };
+
+        if self.last_handled.as_ref() == Some(&source) {
+            self.pending = None;
+            return Recheck::OnChange;
+        }
+
+        if let Some(lock_path) = &self.lock_path {
+            if tokio::fs::try_exists(lock_path).await.unwrap_or(false) {
+                debug!("Lock file {} present, postponing import", lock_path.display());
+                self.pending = None;
+                return Recheck::OnChange;
+            }
+        }
+
+        // Looked at again after the file check interval even without
+        // notifications, as the temporary file may go stale
+        if let Some(temp) = rsync_temp_file(&self.csv_path, self.rsync_stale).await {
+            warn!(
+                "rsync transfer to {} in progress, postponing import until it finishes or {} is untouched for {}s",
+                self.csv_path.display(),
+                temp.display(),
+                self.rsync_stale.as_secs()
+            );
+            self.pending = None;
+            return Recheck::Retry;
+        }
+
+        // Only import once size and mtime are the same on two consecutive
+        // checks, so a file still being written isn't picked up halfway.
+        if self.pending.as_ref() != Some(&source) {
+            debug!("Waiting for {} to settle before importing", self.csv_path.display());
+            self.pending = Some(source);
+            return Recheck::Settling;
+        }
+        self.pending = None;
+
+        if self.run_import(&source, None).await.handled() {
+            self.last_handled = Some(source);
+            Recheck::OnChange
+        } else {
+            Recheck::Retry
+        }
+    }
+
+    // Import a file once, without waiting for it to settle, as the import
+    // command does.
+    pub async fn import_file(&self, path: &Path) -> ImportOutcome {
+        match SourceFile::read(path).await {
+            Ok(source) => self.run_import(&source, None).await,
+            Err(e) => {
+                error!("Failed to read {}: {}", path.display(), e);
+                ImportOutcome::Failed
+            }
+        }
+    }
+
+    #[instrument(name = "import", skip_all, fields(path = %source.path.display()))]
+    async fn run_import(&self, source: &SourceFile, run_id: Option<i64>) -> ImportOutcome {
+        self.progress.start(source.size);
+
+        let run_id = match start_run(&self.pool, source, run_id).await {
+            Ok(id) => {
+                self.progress.set_run_id(id);
+                Some(id)
+            }
+            Err(e) => {
+                error!("Failed to record import run: {}", e);
+                None
+            }
+        };
+
+        let start = Instant::now();
+        info!("Importing database...");
+
+        // Dropping the import rolls its transaction back
+        let result = tokio::select! {
+            result = import_csv(&self.pool, &source.path, run_id, self.options, &self.progress) => result,
+            _ = self.interrupt.cancelled() => Err(ImportError::Interrupted),
+        };
+        let duration = start.elapsed();
+
+        if let Some(id) = run_id {
+            if let Err(e) = finish_run(&self.pool, id, duration, &result).await {
+                error!("Failed to record import run: {}", e);
+            }
+        }
+
+        let outcome = match result {
+            Ok(stats) => {
+                match (stats.inserted, stats.updated, stats.deleted, stats.index_build) {
+                    (Some(inserted), Some(updated), Some(deleted), _) => info!(
+                        "Imported {} rows in {}ms ({} inserted, {} updated, {} deleted)",
+                        stats.rows,
+                        duration.as_millis(),
+                        inserted,
+                        updated,
+                        deleted
+                    ),
+                    (_, _, _, Some(index_build)) => info!(
+                        "Imported {} rows in {}ms, {}ms building indexes",
+                        stats.rows,
+                        duration.as_millis(),
+                        index_build.as_millis()
+                    ),
+                    _ => info!("Imported {} rows in {}ms", stats.rows, duration.as_millis()),
+                }
+
+                let metrics = metrics::get();
+                metrics.import_duration.with_label_values(&["success"]).observe(duration.as_secs_f64());
+                metrics.import_last_success.set(Utc::now().timestamp_millis() as f64 / 1000.0);
+                metrics.import_rows.set(stats.rows as i64);
+                metrics.set_dataset_time(DateTime::from(source.modified));
+
+                self.progress.stage(Stage::Vacuuming);
+                tokio::select! {
+                    result = sqlx::query(r#"VACUUM "sponsorTimes""#).execute(&self.pool) => {
+                        if let Err(e) = result {
+                            error!("Failed to vacuum database: {}", e);
+                            metrics.import_failures.with_label_values(&[Stage::Vacuuming.step()]).inc();
+                        }
+                    }
+                    _ = self.interrupt.cancelled() => info!("Vacuum interrupted, the import itself is complete"),
+                }
+
+                update_category_metrics(&self.pool).await;
+                ImportOutcome::Imported
+            }
+            Err(ImportError::Rejected(r)) => {
+                warn!("Rejected import of {}, keeping existing data: {}", source.path.display(), r);
+                let metrics = metrics::get();
+                metrics.import_duration.with_label_values(&["rejected"]).observe(duration.as_secs_f64());
+                metrics.import_rejections.with_label_values(&[r.reason()]).inc();
+                ImportOutcome::Rejected
+            }
+            Err(ImportError::Interrupted) => {
+                let stage = self.progress.current_stage().unwrap_or(Stage::Starting);
+                warn!(
+                    "Import of {} interrupted while {}, rolled back and keeping existing data",
+                    source.path.display(),
+                    stage.as_str()
+                );
+                let metrics = metrics::get();
+                metrics.import_duration.with_label_values(&["interrupted"]).observe(duration.as_secs_f64());
+                ImportOutcome::Failed
+            }
+            Err(e) => {
+                error!("Failed to import database: {}", e);
+                let metrics = metrics::get();
+                metrics.import_duration.with_label_values(&["failed"]).observe(duration.as_secs_f64());
+                let stage = self.progress.current_stage().unwrap_or(Stage::Starting);
+                metrics.import_failures.with_label_values(&[stage.step()]).inc();
+                ImportOutcome::Failed
+            }
+        };
+
+        self.progress.finish();
+        outcome
+    }
+}
+
+pub enum ImportOutcome {
+    Imported,
+    Rejected,
+    Failed,
+}
+
+impl ImportOutcome {
+    // Whether this version of the file is done with: either it was imported,
+    // or it was rejected and only a newer dump is worth retrying.
+    fn handled(&self) -> bool {
+        !matches!(self, ImportOutcome::Failed)
+    }
+}
+
+// When the file needs another look, short of a notification that it changed.
+enum Recheck {
+    OnChange,
+    Settling,
+    Retry,
+}
+
+#[derive(Clone, PartialEq)]
+struct SourceFile {
+    path: PathBuf,
+    modified: SystemTime,
+    size: u64,
+}
+
+impl SourceFile {
+    async fn read(path: &Path) -> std::io::Result<Self> {
+        let metadata = tokio::fs::metadata(path).await?;
+        Ok(SourceFile {
+            path: path.to_path_buf(),
+            modified: truncate_to_micros(metadata.modified()?),
+            size: metadata.len(),
+        })
+    }
+}
+
+// Postgres keeps timestamps to the microsecond. Truncating modification
+// times the same way lets a source read back from import_runs compare equal
+// to the file it was recorded from.
+fn truncate_to_micros(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => UNIX_EPOCH + Duration::from_micros(since.as_micros() as u64),
+        Err(_) => time,
+    }
+}
+
+// rsync writes into a hidden temporary file next to the destination (e.g.
+// ".sponsorTimes.csv.Gx8a2B") and renames it over the destination once the
+// transfer completes. While such a file is being written a new dump is on its
+// way; one untouched for longer than stale_after was left behind by a
+// transfer that was killed, and is ignored.
+async fn rsync_temp_file(path: &Path, stale_after: Duration) -> Option<PathBuf> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return None;
+    };
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let prefix = format!(".{}.", name.to_string_lossy());
+
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return None;
+    };
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if !entry.file_name().to_string_lossy().starts_with(&prefix) {
+            continue;
+        }
+        // Gone already, most likely renamed into place
+        let Ok(modified) = entry.metadata().await.and_then(|metadata| metadata.modified()) else {
+            continue;
+        };
+        match modified.elapsed() {
+            Ok(age) if age >= stale_after => {
+                debug!("Ignoring {}, untouched for {}s", entry.path().display(), age.as_secs());
+            }
+            _ => return Some(entry.path()),
+        }
+    }
+
+    None
+}
+
+// Record the start of an import, either as a new run or by picking up one
+// queued through the admin API.
+async fn start_run(pool: &PgPool, source: &SourceFile, queued: Option<i64>) -> Result<i64, sqlx::Error> {
+    let path = source.path.display().to_string();
+    let modified = DateTime::<Utc>::from(source.modified);
+    let size = source.size as i64;
+
+    match queued {
+        Some(id) => {
+            sqlx::query_scalar(
+                r#"UPDATE import_runs
+                   SET started_at = now(), status = 'running', source_path = $1, source_modified_at = $2, source_size = $3
+                   WHERE id = $4
+                   RETURNING id"#,
+            )
+            .bind(path)
+            .bind(modified)
+            .bind(size)
+            .bind(id)
+            .fetch_one(pool)
+            .await
+        }
+        None => {
+            sqlx::query_scalar(
+                r#"INSERT INTO import_runs (source_path, source_modified_at, source_size)
+                   VALUES ($1, $2, $3)
+                   RETURNING id"#,
+            )
+            .bind(path)
+            .bind(modified)
+            .bind(size)
+            .fetch_one(pool)
+            .await
+        }
+    }
+}
+
+async fn finish_run(
+    pool: &PgPool,
+    id: i64,
+    duration: Duration,
+    result: &Result<ImportStats, ImportError>,
+) -> Result<(), sqlx::Error> {
+    let (status, stats, error) = match result {
+        Ok(stats) => ("success", Some(stats), None),
+        Err(ImportError::Rejected(r)) => ("rejected", None, Some(r.to_string())),
+        Err(ImportError::Interrupted) => ("interrupted", None, Some(ImportError::Interrupted.to_string())),
+        Err(e) => ("failed", None, Some(e.to_string())),
+    };
+    let count = |n: Option<u64>| n.map(|n| n as i64);
+
+    sqlx::query(
+        r#"UPDATE import_runs
+           SET finished_at = now(), duration_ms = $2, status = $3, rows_imported = $4, error = $5,
+               mode = $6, rows_inserted = $7, rows_updated = $8, rows_deleted = $9, index_build_ms = $10
+           WHERE id = $1"#,
+    )
+    .bind(id)
+    .bind(duration.as_millis() as i64)
+    .bind(status)
+    .bind(stats.map(|s| s.rows as i64))
+    .bind(error)
+    .bind(stats.map(|s| s.mode.as_str()))
+    .bind(stats.and_then(|s| count(s.inserted)))
+    .bind(stats.and_then(|s| count(s.updated)))
+    .bind(stats.and_then(|s| count(s.deleted)))
+    .bind(stats.and_then(|s| s.index_build).map(|d| d.as_millis() as i64))
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Runs left queued or running by a process that crashed or was killed never
+// finish on their own. Unless an import holds the table lock, as one really
+// running would, they are marked failed.
+async fn fail_abandoned_runs(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    let locked: bool = sqlx::query_scalar(r#"SELECT pg_try_advisory_xact_lock($1)"#)
+        .bind(TABLE_LOCK)
+        .fetch_one(&mut *transaction)
+        .await?;
+    if !locked {
+        return Ok(0);
+    }
+
+    let failed = sqlx::query(
+        r#"UPDATE import_runs
+           SET finished_at = now(), status = 'failed', error = 'The mirror stopped before the import finished'
+           WHERE status IN ('queued', 'running')"#,
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+
+    transaction.commit().await?;
+    Ok(failed)
+}
+
+pub async fn recent_runs(pool: &PgPool, limit: i64) -> Result<Vec<ImportRun>, sqlx::Error> {
+    sqlx::query_as::<_, ImportRun>(r#"SELECT * FROM import_runs ORDER BY started_at DESC LIMIT $1"#)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn last_successful_run(pool: &PgPool) -> Result<Option<ImportRun>, sqlx::Error> {
+    sqlx::query_as::<_, ImportRun>(
+        r#"SELECT * FROM import_runs WHERE status = 'success' ORDER BY started_at DESC LIMIT 1"#,
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+// The run the data being served comes from: the last successful import, or
+// a rollback since, which carries the source of the dataset it restored.
+pub async fn current_dataset_run(pool: &PgPool) -> Result<Option<ImportRun>, sqlx::Error> {
+    sqlx::query_as::<_, ImportRun>(
+        r#"SELECT * FROM import_runs WHERE status IN ('success', 'rollback') ORDER BY started_at DESC LIMIT 1"#,
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+// The dump set aside by a rollback, as long as nothing has been imported
+// since.
+async fn rolled_back_source(pool: &PgPool) -> Result<Option<SourceFile>, sqlx::Error> {
+    let Some(run) = current_dataset_run(pool).await? else {
+        return Ok(None);
+    };
+    if run.status != "rollback" {
+        return Ok(None);
+    }
+
+    Ok(match (run.replaced_source_path, run.replaced_source_modified_at, run.replaced_source_size) {
+        (Some(path), Some(modified), Some(size)) => Some(SourceFile {
+            path: PathBuf::from(path),
+            modified: SystemTime::from(modified),
+            size: size as u64,
+        }),
+        _ => None,
+    })
+}
+
+fn set_dataset_metrics(run: &ImportRun) {
+    let metrics = metrics::get();
+    metrics.import_rows.set(run.rows_imported.unwrap_or(0));
+    metrics.set_dataset_time(dataset_time(run));
+}
+
+async fn update_category_metrics(pool: &PgPool) {
+    match category_counts(pool).await {
+        Ok(counts) => {
+            let dataset_rows = &metrics::get().dataset_rows;
+            dataset_rows.reset();
+            for (category, count) in counts {
+                dataset_rows.with_label_values(&[&category]).set(count);
+            }
+        }
+        Err(e) => error!("Failed to count segments by category: {}", e),
+    }
+}
+
+// Age of the data being served, measured from the modification time of the
+// dump it was imported from. Falls back to the import time when the dump's
+// mtime wasn't recorded.
+pub fn dataset_age(run: &ImportRun) -> chrono::Duration {
+    Utc::now() - dataset_time(run)
+}
+
+fn dataset_time(run: &ImportRun) -> DateTime<Utc> {
+    run.source_modified_at.or(run.finished_at).unwrap_or(run.started_at)
+}
+
+async fn category_counts(pool: &PgPool) -> Result<Vec<(String, i64)>, sqlx::Error> {
+    sqlx::query_as(r#"SELECT category, COUNT(*) FROM "sponsorTimes" GROUP BY category"#)
+        .fetch_all(pool)
+        .await
+}
+
+// Whether there is anything to serve, for when no import has been recorded,
+// e.g. when the table was restored from a backup.
+pub async fn has_segments(pool: &PgPool) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(r#"SELECT EXISTS (SELECT 1 FROM "sponsorTimes")"#)
+        .fetch_one(pool)
+        .await
+}
+
+// Row count of the live table according to planner statistics. An exact
+// COUNT(*) takes seconds on the full dataset, which is too slow for a status
+// endpoint; the estimate is refreshed by the VACUUM after every import.
+pub async fn estimated_row_count(pool: &PgPool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(r#"SELECT GREATEST(reltuples, 0)::bigint FROM pg_class WHERE oid = '"sponsorTimes"'::regclass"#)
+        .fetch_one(pool)
+        .await
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(std::io::Error),
+    Database(sqlx::Error),
+    Rejected(Rejection),
+    Interrupted,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Io(e) => write!(f, "I/O error: {}", e),
+            ImportError::Database(e) => write!(f, "database error: {}", e),
+            ImportError::Rejected(r) => write!(f, "rejected: {}", r),
+            ImportError::Interrupted => write!(f, "interrupted by shutdown"),
+        }
+    }
+}
+
+impl From<std::io::Error> for ImportError {
+    fn from(e: std::io::Error) -> Self {
+        ImportError::Io(e)
+    }
+}
+
+impl From<sqlx::Error> for ImportError {
+    fn from(e: sqlx::Error) -> Self {
+        ImportError::Database(e)
+    }
+}
+
+// Reasons to refuse swapping a freshly loaded dump in place of the live
+// table, typically because the file was truncated or is not a sponsorTimes
+// dump at all.
+#[derive(Debug)]
+pub enum Rejection {
+    Header(String),
+    TooFewRows { rows: u64, min_rows: u64 },
+    Shrink { rows: u64, current: u64, max_ratio: f64 },
+}
+
+impl Rejection {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Rejection::Header(_) => "header",
+            Rejection::TooFewRows { .. } => "min_rows",
+            Rejection::Shrink { .. } => "shrink",
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Header(header) => write!(f, "unexpected CSV header: {}", header),
+            Rejection::TooFewRows { rows, min_rows } => {
+                write!(f, "dump has {} rows, fewer than the minimum of {}", rows, min_rows)
+            }
+            Rejection::Shrink { rows, current, max_ratio } => write!(
+                f,
+                "dump has {} rows, shrinking the table from {} by more than {}%",
+                rows,
+                current,
+                max_ratio * 100.0
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ImportChecks {
+    min_rows: u64,
+    max_shrink_ratio: f64,
+}
+
+impl ImportChecks {
+    fn check(&self, rows: u64, current: u64) -> Result<(), Rejection> {
+        if rows < self.min_rows {
+            return Err(Rejection::TooFewRows { rows, min_rows: self.min_rows });
+        }
+
+        if current > 0 && (rows as f64) < current as f64 * (1.0 - self.max_shrink_ratio) {
+            return Err(Rejection::Shrink { rows, current, max_ratio: self.max_shrink_ratio });
+        }
+
+        Ok(())
+    }
+}
+
+async fn read_header(reader: &mut DumpReader) -> Result<Vec<String>, ImportError> {
+    let mut header = String::new();
+    reader.read_line(&mut header).await?;
+
+    Ok(header
+        .trim_end_matches(['\r', '\n'])
+        .split(',')
+        .map(|column| column.trim_matches('"').to_string())
+        .collect())
+}
+
+// How the columns of a dump line up with the "sponsorTimes" table. Upstream
+// adds columns from time to time; unknown ones are loaded into throwaway
+// columns and dropped again, and known columns missing from the dump get
+// their default value.
+struct ColumnMapping {
+    copy_columns: Vec<String>,
+    ignored: Vec<String>,
+}
+
+impl ColumnMapping {
+    fn new(header: &[String], table_columns: &[(String, bool)]) -> Result<Self, Rejection> {
+        let mut copy_columns = Vec::with_capacity(header.len());
+        let mut ignored = Vec::new();
+
+        for (i, column) in header.iter().enumerate() {
+            if header[..i].contains(column) {
+                return Err(Rejection::Header(format!("duplicate column {}", column)));
+            }
+
+            if table_columns.iter().any(|(name, _)| name == column) {
+                copy_columns.push(quote_ident(column));
+            } else {
+                warn!("Ignoring unknown column {} in CSV dump", column);
+                metrics::get().import_schema_drift.with_label_values(&["unknown", column]).inc();
+                let placeholder = format!("_ignored_{}", i);
+                copy_columns.push(quote_ident(&placeholder));
+                ignored.push(placeholder);
+            }
+        }
+
+        for (name, required) in table_columns {
+            if header.contains(name) {
+                continue;
+            }
+            if *required {
+                return Err(Rejection::Header(format!("missing required column {}", name)));
+            }
+            warn!("Column {} missing from CSV dump, using its default", name);
+            metrics::get().import_schema_drift.with_label_values(&["missing", name]).inc();
+        }
+
+        Ok(ColumnMapping { copy_columns, ignored })
+    }
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+// Columns of the live table, and whether the dump has to provide them because
+// they are NOT NULL without a default.
+async fn table_columns(conn: &mut PgConnection) -> Result<Vec<(String, bool)>, sqlx::Error> {
+    sqlx::query_as(
+        r#"SELECT column_name::text, (is_nullable = 'NO' AND column_default IS NULL)
+           FROM information_schema.columns
+           WHERE table_schema = current_schema() AND table_name = 'sponsorTimes'
+           ORDER BY ordinal_position"#,
+    )
+    .fetch_all(conn)
+    .await
+}
+
+#[derive(Debug)]
+pub struct ImportStats {
+    pub mode: ImportMode,
+    pub rows: u64,
+    // Only swaps build the live table's indexes; incremental imports update
+    // them in place
+    pub index_build: Option<Duration>,
+    pub inserted: Option<u64>,
+    pub updated: Option<u64>,
+    pub deleted: Option<u64>,
+}
+
+impl ImportStats {
+    fn swapped(rows: u64, index_build: Duration) -> Self {
+        ImportStats {
+            mode: ImportMode::Swap,
+            rows,
+            index_build: Some(index_build),
+            inserted: None,
+            updated: None,
+            deleted: None,
+        }
+    }
+}
+
+async fn import_csv(
+    pool: &PgPool,
+    path: &Path,
+    run_id: Option<i64>,
+    options: ImportOptions,
+    progress: &ProgressTracker,
+) -> Result<ImportStats, ImportError> {
+    if options.mode == ImportMode::Swap {
+        return swap_import(pool, path, run_id, options, progress).await;
+    }
+
+    // Nothing to diff against on the first import; a plain load is faster
+    let has_rows: bool = sqlx::query_scalar(r#"SELECT EXISTS (SELECT 1 FROM "sponsorTimes")"#)
+        .fetch_one(pool)
+        .await?;
+    if !has_rows {
+        return swap_import(pool, path, run_id, options, progress).await;
+    }
+
+    match incremental_import(pool, path, run_id, options, progress).await {
+        Err(ImportError::Database(e)) => {
+            warn!("Incremental import failed, falling back to a full swap: {}", e);
+            swap_import(pool, path, run_id, options, progress).await
+        }
+        result => result,
+    }
+}
+
+// Stream the dump through COPY FROM STDIN into a new, index-less table. The
+// file only has to be readable by the mirror, not by the database server, and
+// compressed dumps are decompressed on the way.
+//
+// Only tables that are dropped again afterwards may be unlogged: one swapped
+// in as the live table has to be logged, as streaming standbys can't read
+// unlogged tables.
+async fn load_dump(
+    conn: &mut PgConnection,
+    path: &Path,
+    table: &str,
+    logged: bool,
+    progress: &ProgressTracker,
+) -> Result<u64, ImportError> {
+    progress.stage(Stage::Loading);
+    // A failed incremental import falls back to a swap, which reads the file
+    // a second time
+    progress.bytes_read.store(0, Ordering::Relaxed);
+    let (compression, mut reader) = dump::open(path, progress.bytes_read.clone()).await?;
+    debug!("Reading {} dump {}", compression, path.display());
+    let header = read_header(&mut reader).await?;
+
+    let columns = table_columns(conn).await?;
+    let mapping = ColumnMapping::new(&header, &columns).map_err(ImportError::Rejected)?;
+
+    sqlx::query(&format!(r#"DROP TABLE IF EXISTS {}"#, quote_ident(table)))
+        .execute(&mut *conn)
+        .await?;
+
+    // Indexes are built once the data is in, which is much faster than
+    // maintaining them during the COPY
+    sqlx::query(&format!(
+        r#"CREATE {} TABLE {}(LIKE "sponsorTimes" INCLUDING defaults INCLUDING constraints)"#,
+        if logged { "" } else { "UNLOGGED" },
+        quote_ident(table)
+    ))
+    .execute(&mut *conn)
+    .await?;
+
+    for placeholder in &mapping.ignored {
+        sqlx::query(&format!(r#"ALTER TABLE {} ADD COLUMN {} TEXT"#, quote_ident(table), quote_ident(placeholder)))
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    // The header line has already been consumed from the reader
+    let copy = format!(
+        r#"COPY {} ({}) FROM STDIN WITH (FORMAT csv, DELIMITER ',')"#,
+        quote_ident(table),
+        mapping.copy_columns.join(", ")
+    );
+    let mut copy_in = conn.copy_in_raw(&copy).await?;
+    if let Err(e) = copy_in.read_from(&mut reader).await {
+        let _ = copy_in.abort(e.to_string()).await;
+        return Err(e.into());
+    }
+    let rows = copy_in.finish().await?;
+
+    for placeholder in &mapping.ignored {
+        sqlx::query(&format!(r#"ALTER TABLE {} DROP COLUMN {}"#, quote_ident(table), quote_ident(placeholder)))
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    Ok(rows)
+}
+
+// Imports and rollbacks may run from more than one process, e.g. the import
+// command next to a server. They take turns through an advisory lock held
+// until their transaction ends.
+const TABLE_LOCK: i64 = 0x0053_706f_6e73_6f72;
+
+async fn lock_tables(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query(r#"SELECT pg_advisory_xact_lock($1)"#)
+        .bind(TABLE_LOCK)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+async fn live_row_count(conn: &mut PgConnection) -> Result<u64, sqlx::Error> {
+    let count: i64 = sqlx::query_scalar(r#"SELECT COUNT(*) FROM "sponsorTimes""#)
+        .fetch_one(conn)
+        .await?;
+    Ok(count as u64)
+}
+
+// Load the dump into a fresh table, then swap it in place of the live one.
+// Dropping the transaction without committing rolls everything back, so a
+// failed step or a rejected dump leaves the old data untouched.
+async fn swap_import(
+    pool: &PgPool,
+    path: &Path,
+    run_id: Option<i64>,
+    options: ImportOptions,
+    progress: &ProgressTracker,
+) -> Result<ImportStats, ImportError> {
+    let mut transaction = pool.begin().await?;
+    lock_tables(&mut transaction).await?;
+
+    let rows = load_dump(&mut transaction, path, "sponsorTimesTemp", true, progress).await?;
+
+    progress.stage(Stage::Validating);
+    let current = live_row_count(&mut transaction).await?;
+    options.checks.check(rows, current).map_err(ImportError::Rejected)?;
+
+    progress.stage(Stage::Indexing);
+    let index_build = build_indexes(&mut transaction, "sponsorTimesTemp").await?;
+    mark_dataset(&mut transaction, "sponsorTimesTemp", run_id).await?;
+
+    progress.stage(Stage::Swapping);
+    rotate_generations(&mut transaction, options.keep_generations).await?;
+    if options.keep_generations > 0 {
+        rename_table(&mut transaction, "sponsorTimes", &generation_table(1)).await?;
+    } else {
+        sqlx::query(r#"DROP TABLE "sponsorTimes""#)
+            .execute(&mut *transaction)
+            .await?;
+    }
+
+    rename_table(&mut transaction, "sponsorTimesTemp", "sponsorTimes").await?;
+
+    transaction.commit().await?;
+
+    Ok(ImportStats::swapped(rows, index_build))
+}
+
+// Load the dump into a staging table and apply only the differences to the
+// live table, matching rows by UUID. Most of the dump is unchanged between
+// runs, so this touches far fewer rows than rebuilding the table.
+async fn incremental_import(
+    pool: &PgPool,
+    path: &Path,
+    run_id: Option<i64>,
+    options: ImportOptions,
+    progress: &ProgressTracker,
+) -> Result<ImportStats, ImportError> {
+    let mut transaction = pool.begin().await?;
+    lock_tables(&mut transaction).await?;
+
+    let rows = load_dump(&mut transaction, path, "sponsorTimesStaging", false, progress).await?;
+
+    progress.stage(Stage::Validating);
+    let current = live_row_count(&mut transaction).await?;
+    options.checks.check(rows, current).map_err(ImportError::Rejected)?;
+
+    // The live table is changed in place, so keeping the previous generation
+    // takes a full copy of it
+    progress.stage(Stage::CopyingPrevious);
+    rotate_generations(&mut transaction, options.keep_generations).await?;
+    if options.keep_generations > 0 {
+        let prev = generation_table(1);
+        sqlx::query(&format!(
+            r#"CREATE TABLE {}(LIKE "sponsorTimes" INCLUDING defaults INCLUDING constraints)"#,
+            quote_ident(&prev)
+        ))
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query(&format!(r#"INSERT INTO {} SELECT * FROM "sponsorTimes""#, quote_ident(&prev)))
+            .execute(&mut *transaction)
+            .await?;
+        build_indexes(&mut transaction, &prev).await?;
+        let live_run = dataset_run_id(&mut transaction, "sponsorTimes").await?;
+        mark_dataset(&mut transaction, &prev, live_run).await?;
+    }
+
+    progress.stage(Stage::ApplyingChanges);
+    // The key lets the planner use cheap lookups for the diff below
+    sqlx::query(r#"ALTER TABLE "sponsorTimesStaging" ADD PRIMARY KEY ("UUID")"#)
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query(r#"ANALYZE "sponsorTimesStaging""#)
+        .execute(&mut *transaction)
+        .await?;
+
+    let deleted = sqlx::query(
+        r#"DELETE FROM "sponsorTimes" l
+           WHERE NOT EXISTS (SELECT 1 FROM "sponsorTimesStaging" s WHERE s."UUID" = l."UUID")"#,
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+
+    let columns = table_columns(&mut transaction).await?;
+    let assignments = columns
+        .iter()
+        .filter(|(name, _)| name != "UUID")
+        .map(|(name, _)| format!("{0} = s.{0}", quote_ident(name)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let updated = sqlx::query(&format!(
+        r#"UPDATE "sponsorTimes" l SET {}
+           FROM "sponsorTimesStaging" s
+           WHERE s."UUID" = l."UUID" AND (l.*) IS DISTINCT FROM (s.*)"#,
+        assignments
+    ))
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+
+    let inserted = sqlx::query(
+        r#"INSERT INTO "sponsorTimes"
+           SELECT s.* FROM "sponsorTimesStaging" s
+           WHERE NOT EXISTS (SELECT 1 FROM "sponsorTimes" l WHERE l."UUID" = s."UUID")"#,
+    )
+    .execute(&mut *transaction)
+    .await?
+    .rows_affected();
+
+    sqlx::query(r#"DROP TABLE "sponsorTimesStaging""#)
+        .execute(&mut *transaction)
+        .await?;
+    mark_dataset(&mut transaction, "sponsorTimes", run_id).await?;
+
+    transaction.commit().await?;
+
+    Ok(ImportStats {
+        mode: ImportMode::Incremental,
+        rows,
+        index_build: None,
+        inserted: Some(inserted),
+        updated: Some(updated),
+        deleted: Some(deleted),
+    })
+}
+
+// The indexes of "sponsorTimes" as created by the migrations. Copies of the
+// table (the import's temporary table, previous generations) carry the same
+// indexes with the table name substituted, so every index name is known and
+// the live table always ends up with exactly these names.
+const PRIMARY_KEY: &str = "sponsorTimes_pkey";
+const INDEXES: &[(&str, &str)] = &[
+    ("idx_sponsorTimes_hashedVideoID", r#"("hashedVideoID")"#),
+    ("idx_sponsorTimes_videoID", r#"("videoID")"#),
+    ("idx_sponsorTimes_category", r#"("category")"#),
+    ("idx_sponsorTimes_hidden_shadowHidden", r#"("hidden", "shadowHidden")"#),
+    ("idx_sponsorTimes_votes", r#"("votes")"#),
+];
+
+fn index_name(canonical: &str, table: &str) -> String {
+    canonical.replacen("sponsorTimes", table, 1)
+}
+
+async fn build_indexes(conn: &mut PgConnection, table: &str) -> Result<Duration, sqlx::Error> {
+    let start = Instant::now();
+
+    sqlx::query(&format!(
+        r#"ALTER TABLE {} ADD CONSTRAINT {} PRIMARY KEY ("UUID")"#,
+        quote_ident(table),
+        quote_ident(&index_name(PRIMARY_KEY, table))
+    ))
+    .execute(&mut *conn)
+    .await?;
+
+    for (canonical, columns) in INDEXES {
+        sqlx::query(&format!(
+            "CREATE INDEX {} ON {} {}",
+            quote_ident(&index_name(canonical, table)),
+            quote_ident(table),
+            columns
+        ))
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(start.elapsed())
+}
+
+// Rename a copy of the table along with its indexes. Renaming the primary
+// key's index renames the constraint too.
+async fn rename_table(conn: &mut PgConnection, from: &str, to: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!("ALTER TABLE {} RENAME TO {}", quote_ident(from), quote_ident(to)))
+        .execute(&mut *conn)
+        .await?;
+
+    for canonical in std::iter::once(PRIMARY_KEY).chain(INDEXES.iter().map(|(name, _)| *name)) {
+        sqlx::query(&format!(
+            "ALTER INDEX IF EXISTS {} RENAME TO {}",
+            quote_ident(&index_name(canonical, from)),
+            quote_ident(&index_name(canonical, to))
+        ))
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+// Each copy of the table carries the ID of the import run its data came
+// from as its comment, which follows it through renames, so a rollback can
+// tell which dump it restores.
+async fn mark_dataset(conn: &mut PgConnection, table: &str, run_id: Option<i64>) -> Result<(), sqlx::Error> {
+    let comment = match run_id {
+        Some(id) => format!("'{}'", id),
+        None => "NULL".to_string(),
+    };
+    sqlx::query(&format!("COMMENT ON TABLE {} IS {}", quote_ident(table), comment))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+async fn dataset_run_id(conn: &mut PgConnection, table: &str) -> Result<Option<i64>, sqlx::Error> {
+    let comment: Option<String> = sqlx::query_scalar(r#"SELECT obj_description(to_regclass($1), 'pg_class')"#)
+        .bind(quote_ident(table))
+        .fetch_one(conn)
+        .await?;
+    Ok(comment.and_then(|comment| comment.parse().ok()))
+}
+
+async fn dataset_run(conn: &mut PgConnection, table: &str) -> Result<Option<ImportRun>, sqlx::Error> {
+    let Some(id) = dataset_run_id(conn, table).await? else {
+        return Ok(None);
+    };
+    sqlx::query_as::<_, ImportRun>(r#"SELECT * FROM import_runs WHERE id = $1"#)
+        .bind(id)
+        .fetch_optional(conn)
+        .await
+}
+
+// Previous datasets are kept as "sponsorTimes_prev", "sponsorTimes_prev2", ...
+// with the most recent one first.
+fn generation_table(generation: u32) -> String {
+    match generation {
+        1 => "sponsorTimes_prev".to_string(),
+        n => format!("sponsorTimes_prev{}", n),
+    }
+}
+
+async fn previous_generations(conn: &mut PgConnection) -> Result<Vec<(u32, String)>, sqlx::Error> {
+    let tables: Vec<String> = sqlx::query_scalar(
+        r#"SELECT tablename::text FROM pg_tables
+           WHERE schemaname = current_schema() AND tablename LIKE 'sponsorTimes\_prev%'"#,
+    )
+    .fetch_all(conn)
+    .await?;
+
+    let mut generations: Vec<(u32, String)> = tables
+        .into_iter()
+        .filter_map(|table| {
+            let suffix = table.strip_prefix("sponsorTimes_prev")?;
+            let generation = if suffix.is_empty() { 1 } else { suffix.parse().ok()? };
+            Some((generation, table))
+        })
+        .collect();
+    generations.sort();
+
+    Ok(generations)
+}
+
+// Make room for a new previous generation: drop the ones that fall outside
+// the retention and shift the rest back by one.
+async fn rotate_generations(conn: &mut PgConnection, keep: u32) -> Result<(), sqlx::Error> {
+    let generations = previous_generations(conn).await?;
+
+    for (generation, table) in generations.iter().rev() {
+        if *generation >= keep {
+            // The oldest kept generation making room is routine; anything
+            // else was kept under a higher retention
+            if *generation > keep || keep == 0 {
+                warn!("Dropping {}, beyond IMPORT_KEEP_GENERATIONS ({})", table, keep);
+            }
+            sqlx::query(&format!("DROP TABLE {}", quote_ident(table)))
+                .execute(&mut *conn)
+                .await?;
+        } else {
+            rename_table(conn, table, &generation_table(generation + 1)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+// Swap the live table with the most recent previous generation. The data
+// being replaced becomes the previous generation, so a rollback can itself be
+// undone by rolling back again. Returns false when there is nothing to roll
+// back to.
+//
+// The rollback is recorded with the source of the dataset it restores, which
+// makes it the current dataset, and with the source of the one it replaces,
+// which the importer then leaves alone until a new dump arrives.
+pub async fn rollback(pool: &PgPool) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    // The admin API's pool has a statement timeout, but the lock is held for
+    // as long as a running import takes
+    sqlx::query("SET LOCAL statement_timeout = 0")
+        .execute(&mut *transaction)
+        .await?;
+    lock_tables(&mut transaction).await?;
+
+    let generations = previous_generations(&mut transaction).await?;
+    if !generations.iter().any(|(generation, _)| *generation == 1) {
+        return Ok(false);
+    }
+
+    let prev = generation_table(1);
+    let restored = dataset_run(&mut transaction, &prev).await?;
+    let replaced = dataset_run(&mut transaction, "sponsorTimes").await?;
+
+    rename_table(&mut transaction, "sponsorTimes", "sponsorTimesRollback").await?;
+    rename_table(&mut transaction, &prev, "sponsorTimes").await?;
+    rename_table(&mut transaction, "sponsorTimesRollback", &prev).await?;
+
+    let rows = match restored.as_ref().and_then(|run| run.rows_imported) {
+        Some(rows) => rows,
+        None => live_row_count(&mut transaction).await? as i64,
+    };
+    // Generations kept before their import runs were recorded have no known
+    // source
+    let run = sqlx::query_as::<_, ImportRun>(
+        r#"INSERT INTO import_runs
+               (finished_at, duration_ms, status, mode, rows_imported, source_path, source_modified_at, source_size,
+                replaced_source_path, replaced_source_modified_at, replaced_source_size)
+           VALUES (now(), 0, 'rollback', 'rollback', $1, $2, $3, $4, $5, $6, $7)
+           RETURNING *"#,
+    )
+    .bind(rows)
+    .bind(restored.as_ref().map(|run| run.source_path.clone()).unwrap_or_default())
+    .bind(restored.as_ref().and_then(|run| run.source_modified_at))
+    .bind(restored.as_ref().and_then(|run| run.source_size))
+    .bind(replaced.as_ref().map(|run| run.source_path.clone()))
+    .bind(replaced.as_ref().and_then(|run| run.source_modified_at))
+    .bind(replaced.as_ref().and_then(|run| run.source_size))
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    set_dataset_metrics(&run);
+    update_category_metrics(pool).await;
+
+    Ok(true)
+}
+
+pub async fn find_run(pool: &PgPool, id: i64) -> Result<Option<ImportRun>, sqlx::Error> {
+    sqlx::query_as::<_, ImportRun>(r#"SELECT * FROM import_runs WHERE id = $1"#)
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn table() -> Vec<(String, bool)> {
+        vec![
+            ("videoID".to_string(), true),
+            ("startTime".to_string(), true),
+            ("UUID".to_string(), true),
+            ("votes".to_string(), false),
+        ]
+    }
+
+    #[test]
+    fn mapping_follows_the_header_order() {
+        let mapping = ColumnMapping::new(&strings(&["UUID", "votes", "videoID", "startTime"]), &table()).unwrap();
+        assert_eq!(mapping.copy_columns, strings(&[r#""UUID""#, r#""votes""#, r#""videoID""#, r#""startTime""#]));
+        assert!(mapping.ignored.is_empty());
+    }
+
+    #[test]
+    fn mapping_loads_unknown_columns_into_placeholders() {
+        let mapping = ColumnMapping::new(&strings(&["videoID", "newColumn", "startTime", "UUID", "other"]), &table())
+            .unwrap();
+        assert_eq!(
+            mapping.copy_columns,
+            strings(&[r#""videoID""#, r#""_ignored_1""#, r#""startTime""#, r#""UUID""#, r#""_ignored_4""#])
+        );
+        assert_eq!(mapping.ignored, strings(&["_ignored_1", "_ignored_4"]));
+    }
+
+    #[test]
+    fn mapping_leaves_out_missing_optional_columns() {
+        let mapping = ColumnMapping::new(&strings(&["videoID", "startTime", "UUID"]), &table()).unwrap();
+        assert_eq!(mapping.copy_columns, strings(&[r#""videoID""#, r#""startTime""#, r#""UUID""#]));
+    }
+
+    #[test]
+    fn mapping_rejects_missing_required_columns() {
+        let mapping = ColumnMapping::new(&strings(&["videoID", "UUID", "votes"]), &table());
+        assert!(matches!(mapping, Err(Rejection::Header(message)) if message.contains("startTime")));
+    }
+
+    #[test]
+    fn mapping_rejects_duplicate_columns() {
+        let mapping = ColumnMapping::new(&strings(&["videoID", "startTime", "UUID", "videoID"]), &table());
+        assert!(matches!(mapping, Err(Rejection::Header(message)) if message.contains("duplicate")));
+    }
+
+    #[test]
+    fn mapping_is_case_sensitive() {
+        let mapping = ColumnMapping::new(&strings(&["videoid", "videoID", "startTime", "UUID"]), &table()).unwrap();
+        assert_eq!(mapping.ignored, strings(&["_ignored_0"]));
+    }
+
+    #[test]
+    fn identifiers_are_quoted() {
+        assert_eq!(quote_ident("videoID"), r#""videoID""#);
+        assert_eq!(quote_ident(r#"a"b"#), r#""a""b""#);
+    }
+
+    const CHECKS: ImportChecks = ImportChecks { min_rows: 1000, max_shrink_ratio: 0.5 };
+
+    #[test]
+    fn checks_accept_at_the_minimum_row_count() {
+        assert!(CHECKS.check(1000, 0).is_ok());
+        assert!(matches!(
+            CHECKS.check(999, 0),
+            Err(Rejection::TooFewRows { rows: 999, min_rows: 1000 })
+        ));
+    }
+
+    #[test]
+    fn checks_accept_shrinking_up_to_the_ratio() {
+        assert!(CHECKS.check(5000, 10000).is_ok());
+        assert!(matches!(
+            CHECKS.check(4999, 10000),
+            Err(Rejection::Shrink { rows: 4999, current: 10000, .. })
+        ));
+        assert!(CHECKS.check(20000, 10000).is_ok());
+    }
+
+    #[test]
+    fn checks_apply_the_minimum_before_the_ratio() {
+        assert!(matches!(CHECKS.check(999, 10000), Err(Rejection::TooFewRows { .. })));
+    }
+
+    #[test]
+    fn checks_with_the_limits_off() {
+        let checks = ImportChecks { min_rows: 0, max_shrink_ratio: 1.0 };
+        assert!(checks.check(0, 10000).is_ok());
+
+        let checks = ImportChecks { min_rows: 0, max_shrink_ratio: 0.0 };
+        assert!(checks.check(10000, 10000).is_ok());
+        assert!(matches!(checks.check(9999, 10000), Err(Rejection::Shrink { .. })));
+    }
+}
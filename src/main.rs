@@ -1,85 +1,142 @@
-use std::path::Path;
-use std::sync::Arc;
-use std::thread::sleep;
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::process::ExitCode;
 
 use actix_cors::Cors;
-use actix_web::{web, App, HttpServer, middleware::Logger};
+use actix_web::{web, App, HttpServer, middleware::from_fn};
 use actix_web_prom::PrometheusMetricsBuilder;
-use once_cell::sync::Lazy;
-use sqlx::PgPool;
-use tokio::sync::Mutex;
-use tokio::time::interval;
-use tracing::{info, debug, error};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use clap::Parser;
+use tracing::{info, debug, warn};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
 use structs::{Segment, Sponsor};
 
-use crate::routes::{fake_is_user_vip, fake_user_info, skip_segments, skip_segments_by_id, health_check, ApiDoc};
-use crate::config::Config;
-
+use crate::routes::{fake_is_user_vip, fake_user_info, skip_segments, skip_segments_by_id, health_check, liveness, readiness, mirror_status, ApiDoc};
+use crate::cli::{Cli, Command, ConfigCommand};
+use crate::config::{redact_url, Config};
+use crate::database::ReadPools;
+use crate::downloader::Downloader;
+use crate::importer::Importer;
+use crate::rate_limit::{RateLimiter, TrustedProxies};
+use crate::upstream::Upstream;
+
+mod admin;
+mod cli;
 mod config;
+mod database;
+mod downloader;
+mod dump;
+mod importer;
+mod metrics;
 mod models;
+mod privacy;
+mod rate_limit;
+mod request_id;
 mod routes;
+mod shutdown;
 mod structs;
-
-async fn run_migrations(pool: &PgPool) {
-    sqlx::migrate!("./migrations")
-        .run(pool)
-        .await
-        .expect("Failed to run migrations");
-}
-
-
-static LAST_UPDATE: Lazy<Arc<Mutex<SystemTime>>> =
-    Lazy::new(|| Arc::new(Mutex::new(SystemTime::UNIX_EPOCH)));
+mod telemetry;
+mod upstream;
+mod watcher;
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> std::io::Result<ExitCode> {
     // Load .env file if it exists
     dotenvy::dotenv().ok();
 
+    let cli = Cli::parse();
+    if let Command::Config { command: ConfigCommand::Check } = cli.command() {
+        return Ok(cli::check_config(cli.config.as_deref()));
+    }
+
     // Load configuration
-    let config = Config::from_env().expect("Failed to load configuration");
+    let config = Config::load(cli.config.as_deref()).expect("Failed to load configuration");
     
     // Initialize tracing
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| config.log_level.clone().into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-    
-    debug!("Database connection string: {}", config.database_url);
-    debug!("Server will bind to: {}", config.server_bind_address());
+    let writer = if cli.logs_to_stderr() {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
+    let tracer_provider = telemetry::init(&config, writer);
+
+    debug!("Database connection string: {}", redact_url(&config.database_url));
+
+    let result = match cli.command() {
+        Command::Serve { no_importer } => serve(config, !no_importer).await,
+        Command::Import { file } => cli::import(&config, file).await,
+        Command::Migrate => cli::migrate(&config).await,
+        Command::Query { video_id, hash, categories } => {
+            cli::query(&config, video_id.as_deref(), hash.as_deref(), categories.as_deref()).await
+        }
+        Command::Config { .. } => unreachable!("handled before loading the configuration"),
+    };
 
-    // Create database connection pool
-    let pool = PgPool::connect(&config.database_url)
-        .await
-        .expect("Failed to create database pool");
+    if let Some(provider) = tracer_provider {
+        if let Err(e) = provider.shutdown() {
+            warn!("Failed to send remaining traces: {}", e);
+        }
+    }
 
-    // Run migrations
-    run_migrations(&pool).await;
+    result
+}
 
-    // Start background task
-    let pool_clone = pool.clone();
-    let config_clone = config.clone();
-    tokio::spawn(async move {
-        background_database_task(pool_clone, config_clone).await;
-    });
+async fn serve(config: Config, with_importer: bool) -> std::io::Result<ExitCode> {
+    debug!("Server will bind to: {}", config.server_bind_address());
 
-    info!("Starting server on {}", config.server_bind_address());
+    // Create database connection pools: imports get their own, without the
+    // statement timeout of the API's
+    let pool = database::connect(&config).await;
+    let import_pool = database::connect_for_import(&config).await;
+
+    // Segment lookups go to the read replicas, if any
+    let read_pools = ReadPools::new(&config, pool.clone());
+    read_pools.spawn_health_checks(&config);
+    if !config.database_replica_urls.is_empty() {
+        info!("Reading segments from {} replicas", config.database_replica_urls.len());
+    }
+
+    // Run migrations
+    database::run_migrations(&config, &import_pool).await;
 
-    // Create Prometheus metrics
+    // Create Prometheus metrics before anything records to them
     let prometheus = PrometheusMetricsBuilder::new(&config.metrics_namespace)
-        .endpoint("/metrics")
+        .endpoint(&config.metrics_endpoint)
         .build()
         .unwrap();
+    metrics::init(&config.metrics_namespace, &prometheus.registry)
+        .expect("Failed to register metrics");
+
+    // Start the CSV importer, and the downloader if the mirror fetches the
+    // dump itself
+    let importer = with_importer.then(|| Importer::new(import_pool.clone(), &config).spawn());
+    if importer.is_none() {
+        info!("Importer disabled");
+    }
+    // Without an importer, nothing would pick up what it downloads
+    let downloader = match (&config.csv_download_url, &importer) {
+        (Some(url), Some(importer)) => Some(Downloader::new(url.clone(), importer.client(), &config).spawn()),
+        _ => None,
+    };
+
+    info!("Starting server on {}", config.server_bind_address());
+
+    let bind_address = config.server_bind_address();
+    let shutdown_timeout = config.shutdown_timeout();
+    let upstream = Upstream::new(&config).map(web::Data::new);
+    if upstream.is_none() {
+        info!("Upstream fallback disabled");
+    }
+    let rate_limiter = RateLimiter::new(&config).map(web::Data::new);
+    if rate_limiter.is_none() {
+        info!("Rate limiting disabled");
+    }
+    let trusted_proxies = web::Data::new(TrustedProxies::new(&config));
+    let app_config = web::Data::new(config);
+    let app_read_pools = web::Data::new(read_pools.clone());
+    let importer_client = importer.as_ref().map(|importer| web::Data::new(importer.client()));
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         let cors = Cors::default()
             .allow_any_origin()
             .allow_any_method()
@@ -88,101 +145,84 @@ async fn main() -> std::io::Result<()> {
 
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(app_read_pools.clone())
+            .app_data(app_config.clone())
+            .app_data(trusted_proxies.clone())
+            .configure(|cfg| {
+                if let Some(importer_client) = &importer_client {
+                    cfg.app_data(importer_client.clone());
+                }
+                if let Some(upstream) = &upstream {
+                    cfg.app_data(upstream.clone());
+                }
+                if let Some(rate_limiter) = &rate_limiter {
+                    cfg.app_data(rate_limiter.clone());
+                }
+            })
             .wrap(prometheus.clone())
             .wrap(cors)
-            .wrap(Logger::default())
+            .wrap(from_fn(request_id::request_span))
             .service(
                 SwaggerUi::new("/swagger-ui/{_:.*}")
                     .url("/api-docs/openapi.json", ApiDoc::openapi())
             )
             .route("/health", web::get().to(health_check))
+            .route("/health/live", web::get().to(liveness))
+            .route("/health/ready", web::get().to(readiness))
             .route("/api/skipSegments/{hash}", web::get().to(skip_segments))
             .route("/api/skipSegments", web::get().to(skip_segments_by_id))
             .route("/api/isUserVIP", web::get().to(fake_is_user_vip))
             .route("/api/userInfo", web::get().to(fake_user_info))
+            .route("/api/mirror/status", web::get().to(mirror_status))
+            .route("/admin/rollback", web::post().to(admin::rollback))
+            .route("/admin/import", web::post().to(admin::trigger_import))
+            .route("/admin/import/{id}", web::get().to(admin::import_status))
     })
-    .bind(config.server_bind_address())?
-    .run()
-    .await
-}
-
-async fn background_database_task(pool: PgPool, config: Config) {
-    let mut interval = interval(config.check_interval());
-    let path = Path::new(&config.csv_path);
-
-    loop {
-        interval.tick().await;
-        let mut lock_guard = LAST_UPDATE.lock().await;
-        let locked_last_updated_time = &mut *lock_guard;
-
-        // see if file exists
-        if path.exists() && (*locked_last_updated_time == UNIX_EPOCH || locked_last_updated_time.elapsed().unwrap_or_default() > config.file_check_interval()) {
-
-            // Check last modified time
-            let last_modified = path.metadata().unwrap().modified().unwrap();
-
-            // Check if file was modified since last update
-            if *locked_last_updated_time == UNIX_EPOCH || last_modified > *locked_last_updated_time {
-
-                // Use COPY FROM to import the CSV file
-                let start = Instant::now();
-                info!("Importing database...");
-                
-                let mut transaction = match pool.begin().await {
-                    Ok(tx) => tx,
-                    Err(e) => {
-                        error!("Failed to start transaction: {}", e);
-                        continue;
-                    }
-                };
-
-                let drop_temp = sqlx::query(r#"DROP TABLE IF EXISTS "sponsorTimesTemp""#)
-                    .execute(&mut *transaction)
-                    .await;
-                
-                let create_temp = sqlx::query(r#"CREATE UNLOGGED TABLE "sponsorTimesTemp"(LIKE "sponsorTimes" INCLUDING defaults INCLUDING constraints INCLUDING indexes)"#)
-                    .execute(&mut *transaction)
-                    .await;
-                
-                let copy_data = sqlx::query(&format!(r#"COPY "sponsorTimesTemp" FROM '{}' DELIMITER ',' CSV HEADER"#, path.display()))
-                    .execute(&mut *transaction)
-                    .await;
-                
-                let drop_original = sqlx::query(r#"DROP TABLE "sponsorTimes""#)
-                    .execute(&mut *transaction)
-                    .await;
-                
-                let rename_temp = sqlx::query(r#"ALTER TABLE "sponsorTimesTemp" RENAME TO "sponsorTimes""#)
-                    .execute(&mut *transaction)
-                    .await;
-                
-                let result = drop_temp.and(create_temp).and(copy_data).and(drop_original).and(rename_temp);
-
-                match result {
-                    Ok(_) => {
-                        if let Err(e) = transaction.commit().await {
-                            error!("Failed to commit transaction: {}", e);
-                            continue;
-                        }
-                        info!("Imported database in {}ms", start.elapsed().as_millis());
-                        
-                        // Vacuum the database
-                        if let Err(e) = sqlx::query(r#"VACUUM "sponsorTimes""#).execute(&pool).await {
-                            error!("Failed to vacuum database: {}", e);
-                        }
-                        
-                        *locked_last_updated_time = last_modified;
-                    }
-                    Err(e) => {
-                        error!("Failed to import database: {}", e);
-                        if let Err(rollback_err) = transaction.rollback().await {
-                            error!("Failed to rollback transaction: {}", rollback_err);
-                        }
-                    }
-                }
-            }
+    .bind(bind_address)?
+    .shutdown_timeout(shutdown_timeout.as_secs())
+    .disable_signals()
+    .run();
+    let server_handle = server.handle();
+    let mut server = std::pin::pin!(server);
+
+    // Signals are handled here rather than by actix, so the importer is told
+    // to wrap up while in-flight requests are still being answered.
+    let requested = tokio::select! {
+        result = &mut server => {
+            result?;
+            false
+        }
+        _ = shutdown::requested() => true,
+    };
 
-            sleep(config.file_check_interval());
+    // The server future has to keep running for the stop to go through
+    let stop_server = async {
+        if !requested {
+            return Ok(());
         }
-    }
+        info!(
+            "Shutting down: no longer accepting connections, waiting up to {}s for requests in flight",
+            shutdown_timeout.as_secs()
+        );
+        let ((), result) = tokio::join!(server_handle.stop(true), &mut server);
+        result
+    };
+    let stop_tasks = async {
+        if let Some(downloader) = downloader {
+            downloader.shutdown();
+            downloader.join().await;
+        }
+        if let Some(importer) = importer {
+            importer.stop(shutdown_timeout).await;
+        }
+    };
+    let (result, ()) = tokio::join!(stop_server, stop_tasks);
+
+    info!("Closing database connections");
+    read_pools.close().await;
+    import_pool.close().await;
+
+    result?;
+    info!("Shutdown complete");
+    Ok(ExitCode::SUCCESS)
 }
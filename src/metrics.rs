@@ -0,0 +1,158 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::OnceCell;
+use prometheus::{Gauge, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, PullingGauge, Registry};
+
+// Application metrics, exported next to the HTTP metrics collected by
+// actix-web-prom. They live in a global so the importer and the handlers can
+// record without threading a handle through every call.
+pub struct Metrics {
+    pub import_rejections: IntCounterVec,
+    pub import_schema_drift: IntCounterVec,
+    pub import_last_success: Gauge,
+    pub import_duration: HistogramVec,
+    pub import_rows: IntGauge,
+    pub import_failures: IntCounterVec,
+    pub dataset_rows: IntGaugeVec,
+    pub lookups: IntCounterVec,
+    pub fallback_duration: HistogramVec,
+    pub lookup_segments: HistogramVec,
+    pub requested_categories: IntCounterVec,
+    dataset_age: PullingGauge,
+    dataset_time: Arc<AtomicI64>,
+}
+
+static METRICS: OnceCell<Metrics> = OnceCell::new();
+
+// Imports take from seconds (small incremental changes) to most of an hour
+// (a full swap on slow disks).
+const IMPORT_DURATION_BUCKETS: &[f64] = &[1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0, 1800.0, 3600.0];
+
+const LOOKUP_SEGMENTS_BUCKETS: &[f64] = &[0.0, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0];
+
+// Stands in for "no dataset yet" in dataset_time.
+const UNKNOWN: i64 = i64::MIN;
+
+impl Metrics {
+    fn new(namespace: &str) -> Self {
+        // The age is worked out when /metrics is scraped, so it keeps growing
+        // between imports.
+        let dataset_time = Arc::new(AtomicI64::new(UNKNOWN));
+        let time = dataset_time.clone();
+        let dataset_age = PullingGauge::new(
+            format!("{}_dataset_age_seconds", namespace),
+            "Age of the data being served, from the modification time of its dump",
+            Box::new(move || match time.load(Ordering::Relaxed) {
+                UNKNOWN => f64::NAN,
+                millis => (Utc::now().timestamp_millis() - millis) as f64 / 1000.0,
+            }),
+        )
+        .unwrap();
+
+        Metrics {
+            import_rejections: IntCounterVec::new(
+                Opts::new("import_rejections_total", "CSV imports rejected by pre-swap validation")
+                    .namespace(namespace),
+                &["reason"],
+            )
+            .unwrap(),
+            import_schema_drift: IntCounterVec::new(
+                Opts::new("import_schema_drift_total", "Columns of the CSV dump that didn't match the table")
+                    .namespace(namespace),
+                &["kind", "column"],
+            )
+            .unwrap(),
+            import_last_success: Gauge::with_opts(
+                Opts::new("import_last_success_timestamp_seconds", "Time the last successful import finished")
+                    .namespace(namespace),
+            )
+            .unwrap(),
+            import_duration: HistogramVec::new(
+                HistogramOpts::new("import_duration_seconds", "Time taken by imports")
+                    .namespace(namespace)
+                    .buckets(IMPORT_DURATION_BUCKETS.to_vec()),
+                &["status"],
+            )
+            .unwrap(),
+            import_rows: IntGauge::with_opts(
+                Opts::new("import_rows", "Rows in the last successfully imported dump").namespace(namespace),
+            )
+            .unwrap(),
+            import_failures: IntCounterVec::new(
+                Opts::new("import_failures_total", "Imports that failed, by the stage they failed in")
+                    .namespace(namespace),
+                &["stage"],
+            )
+            .unwrap(),
+            dataset_rows: IntGaugeVec::new(
+                Opts::new("dataset_rows", "Segments being served, by category").namespace(namespace),
+                &["category"],
+            )
+            .unwrap(),
+            lookups: IntCounterVec::new(
+                Opts::new(
+                    "skip_segments_lookups_total",
+                    "Segment lookups by kind, answered locally, forwarded upstream, missed, rate limited or failed",
+                )
+                .namespace(namespace),
+                &["kind", "result"],
+            )
+            .unwrap(),
+            fallback_duration: HistogramVec::new(
+                HistogramOpts::new("skip_segments_fallback_duration_seconds", "Time taken by upstream fallback requests")
+                    .namespace(namespace),
+                &["kind"],
+            )
+            .unwrap(),
+            lookup_segments: HistogramVec::new(
+                HistogramOpts::new("skip_segments_result_segments", "Segments returned by lookups answered locally")
+                    .namespace(namespace)
+                    .buckets(LOOKUP_SEGMENTS_BUCKETS.to_vec()),
+                &["kind"],
+            )
+            .unwrap(),
+            requested_categories: IntCounterVec::new(
+                Opts::new("skip_segments_categories_total", "Categories requested in segment lookups")
+                    .namespace(namespace),
+                &["category"],
+            )
+            .unwrap(),
+            dataset_age,
+            dataset_time,
+        }
+    }
+
+    fn register(&self, registry: &Registry) -> prometheus::Result<()> {
+        registry.register(Box::new(self.import_rejections.clone()))?;
+        registry.register(Box::new(self.import_schema_drift.clone()))?;
+        registry.register(Box::new(self.import_last_success.clone()))?;
+        registry.register(Box::new(self.import_duration.clone()))?;
+        registry.register(Box::new(self.import_rows.clone()))?;
+        registry.register(Box::new(self.import_failures.clone()))?;
+        registry.register(Box::new(self.dataset_rows.clone()))?;
+        registry.register(Box::new(self.dataset_age.clone()))?;
+        registry.register(Box::new(self.lookups.clone()))?;
+        registry.register(Box::new(self.fallback_duration.clone()))?;
+        registry.register(Box::new(self.lookup_segments.clone()))?;
+        registry.register(Box::new(self.requested_categories.clone()))?;
+        Ok(())
+    }
+
+    // Record when the dump behind the data being served was written.
+    pub fn set_dataset_time(&self, time: DateTime<Utc>) {
+        self.dataset_time.store(time.timestamp_millis(), Ordering::Relaxed);
+    }
+}
+
+// Create the metrics under the given namespace and add them to the registry
+// served on /metrics.
+pub fn init(namespace: &str, registry: &Registry) -> prometheus::Result<()> {
+    METRICS.get_or_init(|| Metrics::new(namespace)).register(registry)
+}
+
+// Metrics recorded before (or without) init() are kept but never exported.
+pub fn get() -> &'static Metrics {
+    METRICS.get_or_init(|| Metrics::new("api"))
+}
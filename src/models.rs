@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use serde::Serialize;
 use sqlx::FromRow;
 use utoipa::ToSchema;
@@ -49,3 +50,27 @@ pub struct SponsorTime {
     pub user_agent: String,
     pub description: String,
 }
+
+#[derive(Debug, Serialize, FromRow, ToSchema)]
+pub struct ImportRun {
+    pub id: i64,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub duration_ms: Option<i64>,
+    pub status: String,
+    pub mode: Option<String>,
+    pub rows_imported: Option<i64>,
+    pub rows_inserted: Option<i64>,
+    pub rows_updated: Option<i64>,
+    pub rows_deleted: Option<i64>,
+    pub index_build_ms: Option<i64>,
+    pub source_path: String,
+    pub source_modified_at: Option<DateTime<Utc>>,
+    pub source_size: Option<i64>,
+    pub error: Option<String>,
+    // For rollbacks, the dump whose dataset was rolled back
+    pub replaced_source_path: Option<String>,
+    pub replaced_source_modified_at: Option<DateTime<Utc>>,
+    pub replaced_source_size: Option<i64>,
+}
+
@@ -0,0 +1,103 @@
+use actix_web::http::Uri;
+
+use crate::config::REDACTED;
+
+// Hash prefixes this short match thousands of videos, so they say little
+// about what was watched; longer ones are cut down to this length in logs.
+const LOGGED_PREFIX_LENGTH: usize = 4;
+
+// The request path and query for the access log with LOG_PRIVACY set:
+// videoID parameters are replaced and hash prefixes shortened, so the log
+// doesn't record which videos users watch.
+pub fn redact_uri(uri: &Uri) -> String {
+    let path = match uri.path().strip_prefix("/api/skipSegments/") {
+        Some(prefix) => format!("/api/skipSegments/{}", redact_prefix(prefix)),
+        None => uri.path().to_string(),
+    };
+
+    let Some(query) = uri.query() else {
+        return path;
+    };
+    let query: Vec<String> = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if is_video_id(name) => format!("{}={}", name, REDACTED),
+            _ => pair.to_string(),
+        })
+        .collect();
+    format!("{}?{}", path, query.join("&"))
+}
+
+// Compared the way web::Query decodes it, so an encoded name like video%49D
+// can't get the ID past the redaction.
+fn is_video_id(name: &str) -> bool {
+    form_urlencoded::parse(name.as_bytes())
+        .next()
+        .is_some_and(|(name, _)| name.eq_ignore_ascii_case("videoID"))
+}
+
+// Anything that isn't a hex prefix may well be a video ID sent to the wrong
+// endpoint, and is left out entirely.
+fn redact_prefix(prefix: &str) -> String {
+    if !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+        REDACTED.to_string()
+    } else if prefix.len() > LOGGED_PREFIX_LENGTH {
+        format!("{}{}", &prefix[..LOGGED_PREFIX_LENGTH], REDACTED)
+    } else {
+        prefix.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact(uri: &str) -> String {
+        redact_uri(&uri.parse().unwrap())
+    }
+
+    #[test]
+    fn redacts_video_ids() {
+        assert_eq!(
+            redact("/api/skipSegments?videoID=dQw4w9WgXcQ&categories=%5B%22sponsor%22%5D"),
+            format!("/api/skipSegments?videoID={}&categories=%5B%22sponsor%22%5D", REDACTED)
+        );
+        assert_eq!(redact("/api/skipSegments?videoid=dQw4w9WgXcQ"), format!("/api/skipSegments?videoid={}", REDACTED));
+    }
+
+    #[test]
+    fn redacts_encoded_parameter_names() {
+        assert_eq!(redact("/api/skipSegments?%76ideoID=dQw4w9WgXcQ"), format!("/api/skipSegments?%76ideoID={}", REDACTED));
+        assert_eq!(redact("/api/skipSegments?video%49D=dQw4w9WgXcQ"), format!("/api/skipSegments?video%49D={}", REDACTED));
+    }
+
+    #[test]
+    fn redacts_repeated_parameters() {
+        assert_eq!(
+            redact("/api/skipSegments?videoID=dQw4w9WgXcQ&videoID=eQ_8F4nzyiw"),
+            format!("/api/skipSegments?videoID={}&videoID={}", REDACTED, REDACTED)
+        );
+    }
+
+    #[test]
+    fn shortens_hash_prefixes() {
+        assert_eq!(redact("/api/skipSegments/abcd"), "/api/skipSegments/abcd");
+        assert_eq!(redact("/api/skipSegments/abcdef12"), format!("/api/skipSegments/abcd{}", REDACTED));
+        assert_eq!(
+            redact("/api/skipSegments/abcdef12?categories=%5B%22sponsor%22%5D"),
+            format!("/api/skipSegments/abcd{}?categories=%5B%22sponsor%22%5D", REDACTED)
+        );
+    }
+
+    #[test]
+    fn hides_non_hex_path_segments() {
+        assert_eq!(redact("/api/skipSegments/dQw4w9WgXcQ"), format!("/api/skipSegments/{}", REDACTED));
+    }
+
+    #[test]
+    fn leaves_other_uris_alone() {
+        assert_eq!(redact("/health"), "/health");
+        assert_eq!(redact("/api/skipSegments"), "/api/skipSegments");
+        assert_eq!(redact("/api/mirror/status?limit=5"), "/api/mirror/status?limit=5");
+    }
+}
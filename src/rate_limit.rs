@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::{ready, Ready};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpRequest};
+
+use crate::config::Config;
+
+// Clients whose buckets have filled up again are forgotten this often, so
+// the map doesn't grow with every address ever seen.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// Per-client token buckets for segment lookups, and separately for lookups
+// that fall back to upstream, which cost upstream's capacity rather than
+// ours. Clients are told apart by IP address; IPv6 clients by their /64, as
+// a single host usually has a whole one.
+pub struct RateLimiter {
+    lookups: Option<Buckets>,
+    fallbacks: Option<Buckets>,
+    trusted_proxies: TrustedProxies,
+}
+
+impl RateLimiter {
+    // None when neither limit is set.
+    pub fn new(config: &Config) -> Option<Self> {
+        let lookups = Buckets::new(config.rate_limit_lookups_per_second, config.rate_limit_lookup_burst);
+        let fallbacks = Buckets::new(
+            config.rate_limit_fallbacks_per_minute / 60.0,
+            config.rate_limit_fallback_burst,
+        );
+        if lookups.is_none() && fallbacks.is_none() {
+            return None;
+        }
+
+        Some(RateLimiter {
+            lookups,
+            fallbacks,
+            trusted_proxies: TrustedProxies::new(config),
+        })
+    }
+}
+
+// The proxies in RATE_LIMIT_TRUSTED_PROXIES, whose X-Forwarded-For headers
+// are believed when telling clients apart, for rate limits and in the access
+// log.
+#[derive(Clone)]
+pub struct TrustedProxies(Vec<IpRange>);
+
+impl TrustedProxies {
+    pub fn new(config: &Config) -> Self {
+        TrustedProxies(
+            config
+                .rate_limit_trusted_proxies
+                .iter()
+                .map(|range| range.parse().expect("RATE_LIMIT_TRUSTED_PROXIES was validated"))
+                .collect(),
+        )
+    }
+
+    // The client's address: the peer's, or, when the peer is one of our
+    // proxies, the last address in X-Forwarded-For that isn't. Addresses
+    // further left were added by the client itself and can't be trusted.
+    pub fn client(&self, req: &HttpRequest) -> IpAddr {
+        let peer = req
+            .peer_addr()
+            .map(|addr| addr.ip().to_canonical())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+
+        let mut client = peer;
+        let forwarded: Vec<&str> = req
+            .headers()
+            .get_all("x-forwarded-for")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        for hop in forwarded.iter().rev() {
+            if !self.is_trusted(client) {
+                break;
+            }
+            match parse_hop(hop) {
+                Some(ip) => client = ip,
+                None => break,
+            }
+        }
+
+        client
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|range| range.contains(ip))
+    }
+}
+
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+        .map(|ip| ip.to_canonical())
+}
+
+fn bucket_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & !(u128::MAX >> 64))),
+    }
+}
+
+struct Buckets {
+    // Tokens added per second
+    rate: f64,
+    burst: f64,
+    state: Mutex<BucketsState>,
+}
+
+struct BucketsState {
+    clients: HashMap<IpAddr, Bucket>,
+    last_sweep: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Buckets {
+    // None when the limit is off.
+    fn new(rate: f64, burst: u32) -> Option<Self> {
+        (rate > 0.0).then(|| Buckets {
+            rate,
+            burst: burst as f64,
+            state: Mutex::new(BucketsState {
+                clients: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        })
+    }
+
+    // Take a token from the client's bucket, or say how long until there
+    // will be one.
+    fn take(&self, client: IpAddr) -> Result<(), Duration> {
+        self.take_at(client, Instant::now())
+    }
+
+    fn take_at(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+
+        if now.duration_since(state.last_sweep) >= SWEEP_INTERVAL {
+            state.clients.retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+            state.last_sweep = now;
+        }
+
+        let bucket = state.clients.entry(bucket_key(client)).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * self.rate).min(self.burst)
+    }
+}
+
+// The limits that apply to the client making a request. Every check passes
+// when rate limiting is off.
+pub struct ClientLimits {
+    limiter: Option<web::Data<RateLimiter>>,
+    client: IpAddr,
+}
+
+impl ClientLimits {
+    // For lookups made from the command line
+    pub fn unlimited() -> Self {
+        ClientLimits {
+            limiter: None,
+            client: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        }
+    }
+
+    // Err holds how long the client should wait before retrying.
+    pub fn check_lookup(&self) -> Result<(), Duration> {
+        match self.limiter.as_ref().and_then(|limiter| limiter.lookups.as_ref()) {
+            Some(buckets) => buckets.take(self.client),
+            None => Ok(()),
+        }
+    }
+
+    pub fn check_fallback(&self) -> Result<(), Duration> {
+        match self.limiter.as_ref().and_then(|limiter| limiter.fallbacks.as_ref()) {
+            Some(buckets) => buckets.take(self.client),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromRequest for ClientLimits {
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Infallible>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let limits = match req.app_data::<web::Data<RateLimiter>>() {
+            Some(limiter) => ClientLimits {
+                client: limiter.trusted_proxies.client(req),
+                limiter: Some(limiter.clone()),
+            },
+            None => ClientLimits::unlimited(),
+        };
+        ready(Ok(limits))
+    }
+}
+
+// An address or a CIDR block, e.g. 10.0.0.0/8.
+#[derive(Debug, Clone, Copy)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl IpRange {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (network, prefix) = match s.split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            None => (s, None),
+        };
+        let parsed = network.parse::<IpAddr>().map_err(|_| ())?;
+        let bits: u32 = if parsed.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| ())?,
+            None => bits,
+        };
+        if prefix > bits {
+            return Err(());
+        }
+
+        // Addresses are compared in their canonical form, so an IPv4-mapped
+        // block becomes the IPv4 block it maps
+        let network = parsed.to_canonical();
+        let prefix = if network.is_ipv4() && parsed.is_ipv6() {
+            prefix.checked_sub(96).ok_or(())?
+        } else {
+            prefix
+        };
+        Ok(IpRange { network, prefix })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn range(s: &str) -> IpRange {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn client(trusted: &[&str], peer: &str, forwarded: &[&str]) -> IpAddr {
+        let proxies = TrustedProxies(trusted.iter().map(|s| range(s)).collect());
+        let mut req = TestRequest::default().peer_addr(SocketAddr::new(ip(peer), 40000));
+        for value in forwarded {
+            req = req.append_header(("x-forwarded-for", *value));
+        }
+        proxies.client(&req.to_http_request())
+    }
+
+    #[test]
+    fn untrusted_peer_ignores_forwarded_for() {
+        assert_eq!(client(&[], "203.0.113.7", &["198.51.100.1"]), ip("203.0.113.7"));
+        assert_eq!(client(&["10.0.0.0/8"], "203.0.113.7", &["198.51.100.1"]), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn trusted_peer_uses_last_untrusted_hop() {
+        let trusted = ["10.0.0.0/8"];
+        assert_eq!(client(&trusted, "10.0.0.1", &["198.51.100.1"]), ip("198.51.100.1"));
+        // The client put 6.6.6.6 there itself; our proxies appended the rest
+        assert_eq!(
+            client(&trusted, "10.0.0.1", &["6.6.6.6, 198.51.100.1, 10.0.0.2"]),
+            ip("198.51.100.1")
+        );
+        // Split over several header lines, in order
+        assert_eq!(
+            client(&trusted, "10.0.0.1", &["6.6.6.6, 198.51.100.1", "10.0.0.2"]),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[test]
+    fn trusted_peer_without_usable_hops() {
+        let trusted = ["10.0.0.0/8"];
+        assert_eq!(client(&trusted, "10.0.0.1", &[]), ip("10.0.0.1"));
+        assert_eq!(client(&trusted, "10.0.0.1", &["10.0.0.2"]), ip("10.0.0.2"));
+        assert_eq!(client(&trusted, "10.0.0.1", &["6.6.6.6, unknown"]), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn forwarded_hops_with_ports_and_mapped_addresses() {
+        let trusted = ["10.0.0.0/8"];
+        assert_eq!(client(&trusted, "::ffff:10.0.0.1", &["198.51.100.1:1234"]), ip("198.51.100.1"));
+        assert_eq!(client(&trusted, "10.0.0.1", &["[2001:db8::1]:1234"]), ip("2001:db8::1"));
+        assert_eq!(client(&trusted, "10.0.0.1", &["::ffff:198.51.100.1"]), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn ranges_match_their_prefix() {
+        assert!(range("10.0.0.0/8").contains(ip("10.255.1.2")));
+        assert!(!range("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(range("192.168.1.10/32").contains(ip("192.168.1.10")));
+        assert!(!range("192.168.1.10/32").contains(ip("192.168.1.11")));
+        assert!(range("192.168.1.10").contains(ip("192.168.1.10")));
+        assert!(range("2001:db8::/32").contains(ip("2001:db8:ffff::1")));
+        assert!(range("::1/128").contains(ip("::1")));
+        assert!(!range("::1/128").contains(ip("::2")));
+    }
+
+    #[test]
+    fn zero_prefix_matches_its_whole_family() {
+        assert!(range("0.0.0.0/0").contains(ip("203.0.113.7")));
+        assert!(!range("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(range("::/0").contains(ip("2001:db8::1")));
+        assert!(!range("::/0").contains(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn mapped_ranges_match_ipv4() {
+        assert!(range("::ffff:10.0.0.1").contains(ip("10.0.0.1")));
+        assert!(range("::ffff:10.0.0.0/104").contains(ip("10.1.2.3")));
+        assert!(!range("::ffff:10.0.0.0/104").contains(ip("11.0.0.1")));
+        assert!("::ffff:10.0.0.0/64".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        for s in ["", "10.0.0.0/33", "::/129", "10.0.0.0/", "10.0.0.0/x", "10.0.0/8", "localhost"] {
+            assert!(s.parse::<IpRange>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn ipv6_clients_share_their_64() {
+        assert_eq!(bucket_key(ip("2001:db8:1:2:3:4:5:6")), ip("2001:db8:1:2::"));
+        assert_eq!(bucket_key(ip("203.0.113.7")), ip("203.0.113.7"));
+
+        let buckets = Buckets::new(1.0, 1).unwrap();
+        let now = Instant::now();
+        assert!(buckets.take_at(ip("2001:db8:1:2::1"), now).is_ok());
+        assert!(buckets.take_at(ip("2001:db8:1:2::ffff"), now).is_err());
+        assert!(buckets.take_at(ip("2001:db8:1:3::1"), now).is_ok());
+        assert!(buckets.take_at(ip("203.0.113.7"), now).is_ok());
+        assert!(buckets.take_at(ip("203.0.113.8"), now).is_ok());
+    }
+
+    #[test]
+    fn buckets_refill_at_their_rate() {
+        let buckets = Buckets::new(2.0, 2).unwrap();
+        let client = ip("203.0.113.7");
+        let start = Instant::now();
+
+        assert!(buckets.take_at(client, start).is_ok());
+        assert!(buckets.take_at(client, start).is_ok());
+        assert_eq!(buckets.take_at(client, start), Err(Duration::from_millis(500)));
+        assert_eq!(
+            buckets.take_at(client, start + Duration::from_millis(250)),
+            Err(Duration::from_millis(250))
+        );
+        assert!(buckets.take_at(client, start + Duration::from_millis(500)).is_ok());
+        assert!(buckets.take_at(client, start + Duration::from_millis(500)).is_err());
+
+        // Refills stop at the burst size
+        let later = start + Duration::from_secs(10);
+        assert!(buckets.take_at(client, later).is_ok());
+        assert!(buckets.take_at(client, later).is_ok());
+        assert!(buckets.take_at(client, later).is_err());
+    }
+
+    #[test]
+    fn full_buckets_are_swept() {
+        let buckets = Buckets::new(1.0, 5).unwrap();
+        let start = Instant::now();
+        buckets.take_at(ip("203.0.113.7"), start).unwrap();
+        buckets.take_at(ip("203.0.113.8"), start + SWEEP_INTERVAL - Duration::from_millis(500)).unwrap();
+
+        buckets.take_at(ip("203.0.113.9"), start + SWEEP_INTERVAL).unwrap();
+        let state = buckets.state.lock().unwrap();
+        assert!(!state.clients.contains_key(&ip("203.0.113.7")));
+        assert!(state.clients.contains_key(&ip("203.0.113.8")));
+        assert!(state.clients.contains_key(&ip("203.0.113.9")));
+    }
+
+    #[test]
+    fn zero_rate_turns_the_limit_off() {
+        assert!(Buckets::new(0.0, 10).is_none());
+    }
+}
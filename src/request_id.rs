@@ -0,0 +1,88 @@
+use std::time::Instant;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue, USER_AGENT};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+use tracing::{info, info_span, Instrument};
+use uuid::Uuid;
+
+use crate::config::{Config, REDACTED};
+use crate::privacy;
+use crate::rate_limit::TrustedProxies;
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+// IDs passed in by a proxy are trusted as long as they can't mangle a log
+// line: short, printable ASCII without spaces.
+const MAX_ID_LENGTH: usize = 128;
+
+// Runs each request in a span carrying its ID, so everything logged while
+// handling it, down to the upstream fallback, can be told apart. The ID is
+// taken from X-Request-ID when a proxy in front already assigned one, and
+// echoed back in the response either way. Also logs the request once it has
+// been answered, in place of actix's access log, without video IDs and
+// client addresses when LOG_PRIVACY is set.
+pub async fn request_span(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let id = req
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let span = info_span!("request", request_id = %id);
+    let start = Instant::now();
+    let method = req.method().clone();
+    let private = req.app_data::<web::Data<Config>>().is_some_and(|config| config.log_privacy);
+    let (uri, client_ip) = if private {
+        (privacy::redact_uri(req.uri()), REDACTED.to_string())
+    } else {
+        (
+            req.uri().to_string(),
+            client_ip(&req),
+        )
+    };
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("-")
+        .to_string();
+
+    let mut res = next.call(req).instrument(span.clone()).await?;
+
+    let _entered = span.enter();
+    info!(
+        status = res.status().as_u16(),
+        duration_ms = start.elapsed().as_millis() as u64,
+        client_ip = %client_ip,
+        user_agent = %user_agent,
+        "{} {} {}",
+        method,
+        uri,
+        res.status().as_u16()
+    );
+
+    // Validated or generated above, so always a valid header value
+    res.headers_mut().insert(X_REQUEST_ID, HeaderValue::from_str(&id).unwrap());
+    Ok(res)
+}
+
+// Resolved the same way as for rate limits, so a client can't put another
+// address in the log by sending its own X-Forwarded-For.
+fn client_ip(req: &ServiceRequest) -> String {
+    match req.app_data::<web::Data<TrustedProxies>>() {
+        Some(proxies) => proxies.client(req.request()).to_string(),
+        None => req.peer_addr().map_or("-".to_string(), |addr| addr.ip().to_string()),
+    }
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_ID_LENGTH && id.bytes().all(|b| b.is_ascii_graphic())
+}
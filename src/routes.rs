@@ -1,13 +1,24 @@
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
-use actix_web::{web, HttpResponse, Result};
+use actix_web::http::header::{CACHE_CONTROL, RETRY_AFTER};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, HttpResponseBuilder, Result};
 use lazy_static::lazy_static;
+use sha2::{Digest, Sha256};
 use sqlx::PgPool;
+use tracing::{error, info_span, instrument, warn, Instrument};
 use utoipa::OpenApi;
 
 use crate::{Segment, Sponsor};
-use crate::models::SponsorTime;
-use crate::structs::{HealthResponse, HealthChecks, HealthCheck};
+use crate::config::Config;
+use crate::database::ReadPools;
+use crate::importer;
+use crate::metrics;
+use crate::rate_limit::ClientLimits;
+use crate::upstream::{Upstream, UpstreamResponse};
+use crate::models::{ImportRun, SponsorTime};
+use crate::structs::{HealthResponse, HealthChecks, HealthCheck, ImportProgress, ImportRequest, ImportStatus, ImportSummary, MirrorStatus};
 
 #[derive(OpenApi)]
 #[openapi(
@@ -17,15 +28,23 @@ use crate::structs::{HealthResponse, HealthChecks, HealthCheck};
         fake_is_user_vip,
         fake_user_info,
         health_check,
+        liveness,
+        readiness,
+        mirror_status,
+        crate::admin::rollback,
+        crate::admin::trigger_import,
+        crate::admin::import_status,
         metrics
     ),
     components(
-        schemas(Sponsor, Segment, SponsorTime, HealthResponse, HealthChecks, HealthCheck)
+        schemas(Sponsor, Segment, SponsorTime, HealthResponse, HealthChecks, HealthCheck, MirrorStatus, ImportSummary, ImportRun, ImportRequest, ImportStatus, ImportProgress)
     ),
     tags(
         (name = "Skip Segments", description = "SponsorBlock segment retrieval endpoints"),
         (name = "User Info", description = "User information endpoints (mocked for ReVanced compatibility)"),
         (name = "Health", description = "Service health monitoring endpoints"),
+        (name = "Mirror", description = "Mirror import status endpoints"),
+        (name = "Admin", description = "Mirror administration, authenticated with the ADMIN_TOKEN bearer token"),
         (name = "Metrics", description = "Prometheus metrics endpoints")
     ),
     info(
@@ -53,6 +72,119 @@ enum VideoName {
     ByID(String),
 }
 
+impl VideoName {
+    // Label for the lookup metrics
+    fn kind(&self) -> &'static str {
+        match self {
+            VideoName::ByHashPrefix(_) => "hash",
+            VideoName::ByID(_) => "video_id",
+        }
+    }
+}
+
+// Categories known to SponsorBlock. Anything else a client asks for is
+// counted as "other", so the metric labels can't grow without bound.
+const KNOWN_CATEGORIES: &[&str] = &[
+    "sponsor",
+    "selfpromo",
+    "interaction",
+    "intro",
+    "outro",
+    "preview",
+    "music_offtopic",
+    "filler",
+    "poi_highlight",
+    "exclusive_access",
+    "chapter",
+];
+
+fn category_label(category: &str) -> &str {
+    if KNOWN_CATEGORIES.contains(&category) {
+        category
+    } else {
+        "other"
+    }
+}
+
+// Successful lookups may be cached by clients and proxies for
+// CACHE_MAX_AGE_SECONDS.
+fn cacheable(mut builder: HttpResponseBuilder, config: &Config) -> HttpResponseBuilder {
+    if config.cache_max_age_seconds > 0 {
+        builder.insert_header((CACHE_CONTROL, format!("public, max-age={}", config.cache_max_age_seconds)));
+    }
+    builder
+}
+
+// Pass the upstream response on to the client, status included: upstream
+// answers 404 when it has no segments either.
+fn forward(response: reqwest::Result<UpstreamResponse>, config: &Config) -> HttpResponse {
+    match response {
+        Ok(response) if response.status == 200 => cacheable(HttpResponse::Ok(), config)
+            .content_type("application/json")
+            .body(response.body),
+        Ok(response) => HttpResponse::build(StatusCode::from_u16(response.status).unwrap_or(StatusCode::BAD_GATEWAY))
+            .body(response.body),
+        Err(e) => {
+            let e = if config.log_privacy { e.without_url() } else { e };
+            warn!("Upstream request failed: {}", e);
+            HttpResponse::BadGateway().body("Upstream server unavailable")
+        }
+    }
+}
+
+// Look a video up upstream by the prefix of its hash, as clients of the hash
+// endpoint do, so upstream can't tell which video was asked for: the video
+// ID itself never leaves the mirror. The video's segments are picked out of
+// the response here.
+async fn forward_by_hash(upstream: &Upstream, video_id: &str, categories: &str, config: &Config) -> HttpResponse {
+    let hash = hex::encode(Sha256::digest(video_id.as_bytes()));
+    let response = match upstream.skip_segments(&hash[..4], categories).await {
+        Ok(response) if response.status == 200 => response,
+        other => return forward(other, config),
+    };
+
+    // Segments are passed on as upstream sent them
+    let videos: Vec<serde_json::Value> = match serde_json::from_str(&response.body) {
+        Ok(videos) => videos,
+        Err(e) => {
+            warn!("Invalid response from upstream: {}", e);
+            return HttpResponse::BadGateway().body("Upstream server unavailable");
+        }
+    };
+
+    match videos.into_iter().find(|video| video["hash"] == hash.as_str()) {
+        Some(mut video) => cacheable(HttpResponse::Ok(), config).json(video["segments"].take()),
+        None => HttpResponse::NotFound().body("Not Found"),
+    }
+}
+
+// Retry-After is in whole seconds, rounded up so the retry finds a token.
+fn rate_limited(kind: &str, retry_after: Duration) -> HttpResponse {
+    metrics::get().lookups.with_label_values(&[kind, "rate_limited"]).inc();
+    HttpResponse::TooManyRequests()
+        .insert_header((RETRY_AFTER, retry_after.as_secs_f64().ceil().max(1.0).to_string()))
+        .body("Too Many Requests")
+}
+
+// Failed lookups, most likely timed out or without a database to read from,
+// are worth a retry, so they answer 503 rather than 500.
+fn lookup_failed(kind: &str, e: sqlx::Error) -> HttpResponse {
+    error!("Failed to query sponsor times: {}", e);
+    metrics::get().lookups.with_label_values(&[kind, "error"]).inc();
+    HttpResponse::ServiceUnavailable().body("Failed to query sponsor times")
+}
+
+fn parse_categories(categories: Option<&String>) -> Option<Vec<String>> {
+    serde_json::from_str(categories.map(|s| s.as_str()).unwrap_or("[\"sponsor\"]")).ok()
+}
+
+fn record_local_hit(kind: &str, sponsors: &[Sponsor]) {
+    let metrics = metrics::get();
+    metrics.lookups.with_label_values(&[kind, "local"]).inc();
+    let segments: usize = sponsors.iter().map(|sponsor| sponsor.segments.len()).sum();
+    metrics.lookup_segments.with_label_values(&[kind]).observe(segments as f64);
+}
+
 
 #[utoipa::path(
     get,
@@ -65,14 +197,21 @@ enum VideoName {
     ),
     responses(
         (status = 200, description = "List of sponsors with segments", body = [Sponsor]),
-        (status = 400, description = "Invalid hash format")
+        (status = 400, description = "Invalid hash format or categories"),
+        (status = 404, description = "No segments found"),
+        (status = 429, description = "Rate limited; retry after the number of seconds in Retry-After"),
+        (status = 502, description = "Upstream fallback failed"),
+        (status = 503, description = "Segments could not be read from the database")
     ),
     tag = "Skip Segments"
 )]
 pub async fn skip_segments(
     path: web::Path<String>,
     query: web::Query<HashMap<String, String>>,
-    db: web::Data<PgPool>,
+    db: web::Data<ReadPools>,
+    config: web::Data<Config>,
+    upstream: Option<web::Data<Upstream>>,
+    limits: ClientLimits,
 ) -> Result<HttpResponse> {
     let hash = path.into_inner().to_lowercase();
     let categories = query.get("categories");
@@ -81,26 +220,42 @@ pub async fn skip_segments(
     if !HASH_RE.is_match(&hash) {
         return Ok(HttpResponse::BadRequest().body("Hash prefix does not match format requirements."));
     }
+    let Some(category_list) = parse_categories(categories) else {
+        return Ok(HttpResponse::BadRequest().body("categories must be a JSON array of strings"));
+    };
 
-    let sponsors = find_skip_segments(VideoName::ByHashPrefix(hash.clone()), categories.map(|s| s.as_str()), &db).await;
+    let name = VideoName::ByHashPrefix(hash.clone());
+    let kind = name.kind();
+    if let Err(retry_after) = limits.check_lookup() {
+        return Ok(rate_limited(kind, retry_after));
+    }
+    let sponsors = match find_skip_segments(name, &category_list, &db).await {
+        Ok(sponsors) => sponsors,
+        Err(e) => return Ok(lookup_failed(kind, e)),
+    };
 
     if sponsors.is_empty() {
+        let Some(upstream) = upstream else {
+            metrics::get().lookups.with_label_values(&[kind, "miss"]).inc();
+            return Ok(HttpResponse::NotFound().body("Not Found"));
+        };
+        if let Err(retry_after) = limits.check_fallback() {
+            return Ok(rate_limited(kind, retry_after));
+        }
+        metrics::get().lookups.with_label_values(&[kind, "fallback"]).inc();
+        let timer = metrics::get().fallback_duration.with_label_values(&[kind]).start_timer();
+
         // Fall back to central Sponsorblock server
-        let resp = reqwest::get(format!(
-            "https://sponsor.ajay.app/api/skipSegments/{}?categories={}",
-            hash,
-            categories.map(|s| s.as_str()).unwrap_or("[\"sponsor\"]"),
-        ))
-            .await
-            .unwrap()
-            .text()
-            .await
-            .unwrap();
-
-        return Ok(HttpResponse::Ok().content_type("application/json").body(resp));
+        let response = upstream
+            .skip_segments(&hash, categories.map(|s| s.as_str()).unwrap_or("[\"sponsor\"]"))
+            .await;
+
+        timer.observe_duration();
+        return Ok(forward(response, &config));
     }
 
-    Ok(HttpResponse::Ok().json(&sponsors))
+    record_local_hit(kind, &sponsors);
+    Ok(cacheable(HttpResponse::Ok(), &config).json(&sponsors))
 }
 
 #[utoipa::path(
@@ -112,13 +267,20 @@ pub async fn skip_segments(
     ),
     responses(
         (status = 200, description = "List of segments for the video", body = [Segment]),
-        (status = 400, description = "Invalid or missing videoID")
+        (status = 400, description = "Invalid or missing videoID, or invalid categories"),
+        (status = 404, description = "No segments found"),
+        (status = 429, description = "Rate limited; retry after the number of seconds in Retry-After"),
+        (status = 502, description = "Upstream fallback failed"),
+        (status = 503, description = "Segments could not be read from the database")
     ),
     tag = "Skip Segments"
 )]
 pub async fn skip_segments_by_id(
     query: web::Query<HashMap<String, String>>,
-    db: web::Data<PgPool>,
+    db: web::Data<ReadPools>,
+    config: web::Data<Config>,
+    upstream: Option<web::Data<Upstream>>,
+    limits: ClientLimits,
 ) -> Result<HttpResponse> {
     let video_id = match query.get("videoID") {
         Some(id) => id,
@@ -130,74 +292,105 @@ pub async fn skip_segments_by_id(
     if !ID_RE.is_match(video_id) {
         return Ok(HttpResponse::BadRequest().body("videoID does not match format requirements"));
     }
+    let Some(category_list) = parse_categories(categories) else {
+        return Ok(HttpResponse::BadRequest().body("categories must be a JSON array of strings"));
+    };
 
-    let sponsors = find_skip_segments(VideoName::ByID(video_id.clone()), categories.map(|s| s.as_str()), &db).await;
+    let name = VideoName::ByID(video_id.clone());
+    let kind = name.kind();
+    if let Err(retry_after) = limits.check_lookup() {
+        return Ok(rate_limited(kind, retry_after));
+    }
+    let sponsors = match find_skip_segments(name, &category_list, &db).await {
+        Ok(sponsors) => sponsors,
+        Err(e) => return Ok(lookup_failed(kind, e)),
+    };
 
     if sponsors.is_empty() {
+        let Some(upstream) = upstream else {
+            metrics::get().lookups.with_label_values(&[kind, "miss"]).inc();
+            return Ok(HttpResponse::NotFound().body("Not Found"));
+        };
+        if let Err(retry_after) = limits.check_fallback() {
+            return Ok(rate_limited(kind, retry_after));
+        }
+        metrics::get().lookups.with_label_values(&[kind, "fallback"]).inc();
+        let timer = metrics::get().fallback_duration.with_label_values(&[kind]).start_timer();
+
         // Fall back to central Sponsorblock server
-        let resp = reqwest::get(format!(
-            "https://sponsor.ajay.app/api/skipSegments?videoID={}&categories={}",
-            video_id,
-            categories.map(|s| s.as_str()).unwrap_or("[\"sponsor\"]"),
-        ))
-            .await
-            .unwrap()
-            .text()
-            .await
-            .unwrap();
-
-        return Ok(HttpResponse::Ok().content_type("application/json").body(resp));
+        let categories = categories.map(|s| s.as_str()).unwrap_or("[\"sponsor\"]");
+        let response = forward_by_hash(&upstream, video_id, categories, &config).await;
+
+        timer.observe_duration();
+        return Ok(response);
     }
 
+    record_local_hit(kind, &sponsors);
+
     // Doing a lookup by video ID should return only one Sponsor object with
     // one list of segments. We need to return just the list of segments.
-    Ok(HttpResponse::Ok().json(&sponsors[0].segments))
+    Ok(cacheable(HttpResponse::Ok(), &config).json(&sponsors[0].segments))
 }
 
+#[instrument(skip_all, fields(kind = name.kind()))]
 async fn find_skip_segments(
     name: VideoName,
-    categories: Option<&str>,
-    db: &PgPool,
-) -> Vec<Sponsor> {
-    let cat: Vec<String> = serde_json::from_str(categories.unwrap_or("[\"sponsor\"]")).unwrap();
+    cat: &[String],
+    db: &ReadPools,
+) -> Result<Vec<Sponsor>, sqlx::Error> {
+    let requested = &metrics::get().requested_categories;
+    for category in cat {
+        requested.with_label_values(&[category_label(category)]).inc();
+    }
 
     if cat.is_empty() {
-        return Vec::new();
+        return Ok(Vec::new());
     }
 
     let results: Vec<SponsorTime> = match name {
         VideoName::ByHashPrefix(hash_prefix) => {
-            sqlx::query_as::<_, SponsorTime>(
-                r#"SELECT * FROM "sponsorTimes" 
-                   WHERE "shadowHidden" = 0 
-                   AND "hidden" = 0 
-                   AND "votes" >= 0 
-                   AND "category" = ANY($1)
-                   AND "hashedVideoID" LIKE $2"#,
-            )
-            .bind(&cat)
-            .bind(format!("{}%", hash_prefix))
-            .fetch_all(db)
-            .await
-            .expect("Failed to query sponsor times")
+            let hash_prefix = &hash_prefix;
+            db.read(|pool| async move {
+                sqlx::query_as::<_, SponsorTime>(
+                    r#"SELECT * FROM "sponsorTimes" 
+                       WHERE "shadowHidden" = 0 
+                       AND "hidden" = 0 
+                       AND "votes" >= 0 
+                       AND "category" = ANY($1)
+                       AND "hashedVideoID" LIKE $2"#,
+                )
+                .bind(cat)
+                .bind(format!("{}%", hash_prefix))
+                .fetch_all(&pool)
+                .await
+            })
+            .instrument(info_span!("query_segments"))
+            .await?
         }
         VideoName::ByID(video_id) => {
-            sqlx::query_as::<_, SponsorTime>(
-                r#"SELECT * FROM "sponsorTimes" 
-                   WHERE "shadowHidden" = 0 
-                   AND "hidden" = 0 
-                   AND "votes" >= 0 
-                   AND "category" = ANY($1)
-                   AND "videoID" = $2"#,
-            )
-            .bind(&cat)
-            .bind(video_id)
-            .fetch_all(db)
-            .await
-            .expect("Failed to query sponsor times")
+            let video_id = &video_id;
+            db.read(|pool| async move {
+                sqlx::query_as::<_, SponsorTime>(
+                    r#"SELECT * FROM "sponsorTimes" 
+                       WHERE "shadowHidden" = 0 
+                       AND "hidden" = 0 
+                       AND "votes" >= 0 
+                       AND "category" = ANY($1)
+                       AND "videoID" = $2"#,
+                )
+                .bind(cat)
+                .bind(video_id)
+                .fetch_all(&pool)
+                .await
+            })
+            .instrument(info_span!("query_segments"))
+            .await?
         }
     };
 
+    // Grouping is CPU-bound and doesn't await, so the span can stay entered
+    let _grouping = info_span!("group_segments", rows = results.len()).entered();
+
     // Create map of Sponsors - Hash, Sponsor
     let mut sponsors: HashMap<String, Sponsor> = HashMap::new();
 
@@ -242,7 +435,7 @@ async fn find_skip_segments(
         sponsor.segments.sort_by(|a, b| a.partial_cmp(b).unwrap());
     }
 
-    sponsors.into_values().collect()
+    Ok(sponsors.into_values().collect())
 }
 
 fn similar_segments(segment: &Segment, hash: &str, segments: &Vec<SponsorTime>) -> Vec<Segment> {
@@ -357,51 +550,231 @@ pub async fn fake_user_info() -> Result<HttpResponse> {
     get,
     path = "/health",
     responses(
-        (status = 200, description = "Service is healthy", body = HealthResponse),
-        (status = 503, description = "Service is unhealthy", body = HealthResponse)
+        (status = 200, description = "Database reachable; the other checks are informational", body = HealthResponse),
+        (status = 503, description = "Database unreachable", body = HealthResponse)
     ),
     tag = "Health"
 )]
-pub async fn health_check(db: web::Data<PgPool>) -> Result<HttpResponse> {
-    use std::time::Instant;
-    
-    let start = Instant::now();
-    
-    // Check database connectivity
-    let db_check = match sqlx::query("SELECT 1").fetch_one(db.as_ref()).await {
-        Ok(_) => HealthCheck {
-            status: "healthy".to_string(),
-            message: Some("Database connection successful".to_string()),
-            response_time_ms: Some(start.elapsed().as_millis() as u64),
-        },
-        Err(e) => HealthCheck {
-            status: "unhealthy".to_string(),
-            message: Some(format!("Database connection failed: {}", e)),
-            response_time_ms: Some(start.elapsed().as_millis() as u64),
-        },
-    };
-    
-    let overall_status = if db_check.status == "healthy" {
-        "healthy"
-    } else {
-        "unhealthy"
-    };
-    
+pub async fn health_check(
+    db: web::Data<ReadPools>,
+    config: web::Data<Config>,
+    upstream: Option<web::Data<Upstream>>,
+) -> Result<HttpResponse> {
+    let (checks, dataset_age_seconds) = run_health_checks(&db, &config, upstream.as_ref().map(|upstream| upstream.get_ref())).await;
+    let healthy = is_healthy(&checks.database);
+    Ok(health_response(healthy, checks, dataset_age_seconds))
+}
+
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    responses(
+        (status = 200, description = "The process is up and serving requests", body = serde_json::Value)
+    ),
+    tag = "Health"
+)]
+pub async fn liveness() -> Result<HttpResponse> {
+    // Deliberately checks nothing else: a database or upstream outage is no
+    // reason to restart the mirror
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "alive"
+    })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    responses(
+        (status = 200, description = "Ready to answer lookups from its own data", body = HealthResponse),
+        (status = 503, description = "Database unreachable, no or stale dataset, or required upstream unavailable", body = HealthResponse)
+    ),
+    tag = "Health"
+)]
+pub async fn readiness(
+    db: web::Data<ReadPools>,
+    config: web::Data<Config>,
+    upstream: Option<web::Data<Upstream>>,
+) -> Result<HttpResponse> {
+    let (checks, dataset_age_seconds) = run_health_checks(&db, &config, upstream.as_ref().map(|upstream| upstream.get_ref())).await;
+
+    // Replicas don't count: reads fall back to the primary
+    let ready = is_healthy(&checks.database)
+        && is_healthy(&checks.dataset)
+        && (!config.upstream_required || checks.upstream.as_ref().is_some_and(is_healthy));
+
+    Ok(health_response(ready, checks, dataset_age_seconds))
+}
+
+const HEALTHY: &str = "healthy";
+const UNHEALTHY: &str = "unhealthy";
+
+fn is_healthy(check: &HealthCheck) -> bool {
+    check.status == HEALTHY
+}
+
+fn check_result(healthy: bool, message: String, start: Instant) -> HealthCheck {
+    HealthCheck {
+        status: if healthy { HEALTHY } else { UNHEALTHY }.to_string(),
+        message: Some(message),
+        response_time_ms: Some(start.elapsed().as_millis() as u64),
+    }
+}
+
+fn health_response(healthy: bool, checks: HealthChecks, dataset_age_seconds: Option<i64>) -> HttpResponse {
     let health_response = HealthResponse {
-        status: overall_status.to_string(),
+        status: if healthy { HEALTHY } else { UNHEALTHY }.to_string(),
         timestamp: chrono::Utc::now().to_rfc3339(),
-        checks: HealthChecks {
-            database: db_check,
-        },
+        dataset_age_seconds,
+        checks,
     };
-    
-    let status_code = if overall_status == "healthy" {
-        actix_web::http::StatusCode::OK
+
+    let status_code = if healthy {
+        StatusCode::OK
     } else {
-        actix_web::http::StatusCode::SERVICE_UNAVAILABLE
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    HttpResponse::build(status_code).json(&health_response)
+}
+
+// Everything /health and /health/ready report on, along with the age of the
+// dataset when it is known.
+async fn run_health_checks(
+    db: &ReadPools,
+    config: &Config,
+    upstream: Option<&Upstream>,
+) -> (HealthChecks, Option<i64>) {
+    // Check database connectivity
+    let start = Instant::now();
+    let database = match sqlx::query("SELECT 1").fetch_one(db.primary()).await {
+        Ok(_) => check_result(true, "Database connection successful".to_string(), start),
+        Err(e) => check_result(false, format!("Database connection failed: {}", e), start),
+    };
+
+    let replicas = check_replicas(db);
+    let (dataset, dataset_age_seconds) = check_dataset(db, config).await;
+
+    let upstream = match upstream {
+        Some(upstream) => {
+            let start = Instant::now();
+            Some(match upstream.check().await {
+                Ok(()) => check_result(true, "Upstream server reachable".to_string(), start),
+                Err(e) => check_result(false, format!("Upstream server unavailable: {}", e), start),
+            })
+        }
+        None => None,
+    };
+
+    let checks = HealthChecks {
+        database,
+        replicas,
+        dataset,
+        upstream,
     };
-    
-    Ok(HttpResponse::build(status_code).json(&health_response))
+    (checks, dataset_age_seconds)
+}
+
+fn check_replicas(db: &ReadPools) -> Option<HealthCheck> {
+    let replicas = db.replica_status();
+    if replicas.is_empty() {
+        return None;
+    }
+
+    // Only counts: /health is public, and even redacted URLs name hosts,
+    // users and databases. Which replicas are out is logged.
+    let healthy = replicas.iter().filter(|replica| replica.healthy).count();
+
+    Some(HealthCheck {
+        status: match healthy {
+            0 => UNHEALTHY,
+            n if n < replicas.len() => "degraded",
+            _ => HEALTHY,
+        }
+        .to_string(),
+        message: Some(if healthy == replicas.len() {
+            format!("All {} replicas healthy", replicas.len())
+        } else {
+            format!("{} of {} replicas healthy", healthy, replicas.len())
+        }),
+        response_time_ms: None,
+    })
+}
+
+// The mirror has data to serve once an import completed (or a rollback
+// restored an earlier one), or, without any recorded import, when the table
+// isn't empty. With HEALTH_MAX_DATASET_AGE_SECONDS set, the data must also be
+// recent enough.
+async fn check_dataset(db: &ReadPools, config: &Config) -> (HealthCheck, Option<i64>) {
+    let start = Instant::now();
+
+    match db.read(|pool| async move { importer::current_dataset_run(&pool).await }).await {
+        Ok(Some(run)) => {
+            let age = importer::dataset_age(&run).num_seconds();
+            let max_age = config.health_max_dataset_age_seconds;
+            let check = if max_age > 0 && age > max_age as i64 {
+                check_result(false, format!("Dataset is {}s old, more than the {}s allowed", age, max_age), start)
+            } else {
+                check_result(true, format!("Dataset is {}s old", age), start)
+            };
+            (check, Some(age))
+        }
+        Ok(None) => {
+            let check = match db.read(|pool| async move { importer::has_segments(&pool).await }).await {
+                Ok(true) => check_result(true, "No import recorded, but segments are present".to_string(), start),
+                Ok(false) => check_result(false, "No dataset imported yet".to_string(), start),
+                Err(e) => check_result(false, format!("Failed to read segments: {}", e), start),
+            };
+            (check, None)
+        }
+        Err(e) => (check_result(false, format!("Failed to read import runs: {}", e), start), None),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/mirror/status",
+    params(
+        ("limit" = Option<i64>, Query, description = "Number of recent import runs to return (default 10, max 100)")
+    ),
+    responses(
+        (status = 200, description = "Dataset age, row count and recent import runs", body = MirrorStatus),
+        (status = 400, description = "Invalid limit"),
+        (status = 500, description = "Failed to read import status")
+    ),
+    tag = "Mirror"
+)]
+pub async fn mirror_status(
+    query: web::Query<HashMap<String, String>>,
+    db: web::Data<PgPool>,
+) -> Result<HttpResponse> {
+    let limit = match query.get("limit").map(|l| l.parse::<i64>()) {
+        None => 10,
+        Some(Ok(limit)) if (1..=100).contains(&limit) => limit,
+        Some(_) => return Ok(HttpResponse::BadRequest().body("limit must be a number between 1 and 100")),
+    };
+
+    let status = async {
+        let current = importer::current_dataset_run(db.as_ref()).await?;
+        let last_success = importer::last_successful_run(db.as_ref()).await?;
+        let row_count = importer::estimated_row_count(db.as_ref()).await?;
+        let imports = importer::recent_runs(db.as_ref(), limit).await?;
+
+        Ok::<_, sqlx::Error>(MirrorStatus {
+            dataset_age_seconds: current.as_ref().map(|run| importer::dataset_age(run).num_seconds()),
+            last_import_at: last_success.and_then(|run| run.finished_at).map(|t| t.to_rfc3339()),
+            row_count,
+            imports: imports.into_iter().map(ImportSummary::from).collect(),
+        })
+    }
+    .await;
+
+    match status {
+        Ok(status) => Ok(HttpResponse::Ok().json(&status)),
+        Err(e) => {
+            error!("Failed to read import status: {}", e);
+            Ok(HttpResponse::InternalServerError().body("Failed to read import status"))
+        }
+    }
 }
 
 #[utoipa::path(
@@ -412,6 +785,7 @@ pub async fn health_check(db: web::Data<PgPool>) -> Result<HttpResponse> {
     ),
     tag = "Metrics"
 )]
+#[allow(dead_code)]
 pub async fn metrics() -> Result<HttpResponse> {
     // This endpoint is handled by actix-web-prom middleware
     // This function is just for OpenAPI documentation
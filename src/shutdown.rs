@@ -0,0 +1,18 @@
+use tokio::signal;
+
+// Resolves once the process is asked to stop: Ctrl-C, or SIGTERM from
+// `docker stop` and Kubernetes.
+pub async fn requested() {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            signal::unix::signal(signal::unix::SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = signal::ctrl_c().await;
+}
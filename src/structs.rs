@@ -1,7 +1,10 @@
 use std::cmp::Ordering;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+use crate::models::ImportRun;
+
 #[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
 pub struct Sponsor {
     pub hash: String,
@@ -43,12 +46,18 @@ impl PartialOrd for Segment {
 pub struct HealthResponse {
     pub status: String,
     pub timestamp: String,
+    pub dataset_age_seconds: Option<i64>,
     pub checks: HealthChecks,
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
 pub struct HealthChecks {
     pub database: HealthCheck,
+    // Only with DATABASE_REPLICA_URLS set
+    pub replicas: Option<HealthCheck>,
+    pub dataset: HealthCheck,
+    // Only with the upstream fallback enabled
+    pub upstream: Option<HealthCheck>,
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
@@ -57,3 +66,71 @@ pub struct HealthCheck {
     pub message: Option<String>,
     pub response_time_ms: Option<u64>,
 }
+
+#[derive(Serialize, ToSchema)]
+pub struct MirrorStatus {
+    pub dataset_age_seconds: Option<i64>,
+    pub last_import_at: Option<String>,
+    pub row_count: i64,
+    pub imports: Vec<ImportSummary>,
+}
+
+// An import run as the public status endpoint shows it: without the path
+// of the dump or the error it failed with, which the admin API still
+// reports.
+#[derive(Serialize, ToSchema)]
+pub struct ImportSummary {
+    pub id: i64,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub duration_ms: Option<i64>,
+    pub status: String,
+    pub mode: Option<String>,
+    pub rows_imported: Option<i64>,
+    pub rows_inserted: Option<i64>,
+    pub rows_updated: Option<i64>,
+    pub rows_deleted: Option<i64>,
+    pub index_build_ms: Option<i64>,
+    pub source_modified_at: Option<DateTime<Utc>>,
+    pub source_size: Option<i64>,
+}
+
+impl From<ImportRun> for ImportSummary {
+    fn from(run: ImportRun) -> Self {
+        ImportSummary {
+            id: run.id,
+            started_at: run.started_at,
+            finished_at: run.finished_at,
+            duration_ms: run.duration_ms,
+            status: run.status,
+            mode: run.mode,
+            rows_imported: run.rows_imported,
+            rows_inserted: run.rows_inserted,
+            rows_updated: run.rows_updated,
+            rows_deleted: run.rows_deleted,
+            index_build_ms: run.index_build_ms,
+            source_modified_at: run.source_modified_at,
+            source_size: run.source_size,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct ImportProgress {
+    pub run_id: Option<i64>,
+    pub stage: String,
+    pub bytes_read: u64,
+    pub bytes_total: u64,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ImportRequest {
+    /// Import this file instead of CSV_PATH
+    pub path: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImportStatus {
+    pub run: ImportRun,
+    pub progress: Option<ImportProgress>,
+}
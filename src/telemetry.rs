@@ -0,0 +1,69 @@
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::{Sampler, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use tracing::{error, Level};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+use crate::config::{Config, LogFormat};
+
+const SERVICE_NAME: &str = "sponsorblock-mirror";
+
+// Set up logging, and span export to an OpenTelemetry collector when
+// TRACING_OTLP_ENDPOINT is set. LOG_LEVEL only filters the logs: spans are
+// exported at info level whatever it is. The returned provider must be shut
+// down before exiting, to send the spans still queued.
+pub fn init(config: &Config, writer: BoxMakeWriter) -> Option<SdkTracerProvider> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| config.log_level.clone().into());
+    let logs = match config.log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().with_writer(writer).boxed(),
+        // Event fields at the top level, next to the request ID of the span
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_writer(writer)
+            .boxed(),
+    };
+
+    let (provider, export_error) = match config.tracing_otlp_endpoint.as_deref().map(|url| provider(config, url)) {
+        Some(Ok(provider)) => (Some(provider), None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+    let traces = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer(SERVICE_NAME))
+            .with_filter(Targets::new().with_target("sponsorblock_mirror", Level::INFO))
+    });
+
+    tracing_subscriber::registry()
+        .with(logs.with_filter(filter))
+        .with(traces)
+        .init();
+
+    // Reported once logging works; the mirror runs on without traces
+    if let Some(e) = export_error {
+        error!("Failed to set up trace export, traces are disabled: {}", e);
+    }
+
+    provider
+}
+
+fn provider(config: &Config, url: &str) -> Result<SdkTracerProvider, opentelemetry_otlp::ExporterBuildError> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", url))
+        .build()?;
+
+    // Requests are sampled as a whole: spans follow the decision made for
+    // their request
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.tracing_sample_ratio)));
+
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(sampler)
+        .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+        .build())
+}
@@ -0,0 +1,88 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::instrument;
+
+use crate::config::Config;
+
+// How long the outcome of the last request stands in for a fresh check.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+// Client for the SponsorBlock server that lookups are forwarded to when the
+// mirror has no segments for them.
+pub struct Upstream {
+    client: reqwest::Client,
+    base_url: String,
+    last_outcome: Mutex<Option<(Instant, Result<(), String>)>>,
+}
+
+pub struct UpstreamResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Upstream {
+    // None when the fallback is disabled.
+    pub fn new(config: &Config) -> Option<Self> {
+        if !config.upstream_enabled {
+            return None;
+        }
+
+        let client = reqwest::Client::builder()
+            .timeout(config.upstream_timeout())
+            .build()
+            .expect("Failed to build HTTP client");
+
+        Some(Upstream {
+            client,
+            base_url: config.upstream_url.clone(),
+            last_outcome: Mutex::new(None),
+        })
+    }
+
+    pub async fn skip_segments(&self, hash: &str, categories: &str) -> Result<UpstreamResponse, reqwest::Error> {
+        self.get(&format!("{}/api/skipSegments/{}", self.base_url, hash), &[("categories", categories)])
+            .await
+    }
+
+    // Whether upstream is answering, going by the last request forwarded to
+    // it, or by a request to its status endpoint when there was none lately.
+    // Health probes come often; this keeps them from adding upstream load.
+    pub async fn check(&self) -> Result<(), String> {
+        if let Some((at, outcome)) = self.last_outcome.lock().unwrap().as_ref() {
+            if at.elapsed() < CHECK_INTERVAL {
+                return outcome.clone();
+            }
+        }
+
+        outcome(&self.get(&format!("{}/api/status", self.base_url), &[]).await)
+    }
+
+    #[instrument(name = "upstream", skip_all, fields(url = %url))]
+    async fn get(&self, url: &str, query: &[(&str, &str)]) -> Result<UpstreamResponse, reqwest::Error> {
+        let result = async {
+            let response = self.client.get(url).query(query).send().await?;
+            let status = response.status().as_u16();
+            let body = response.text().await?;
+            Ok(UpstreamResponse { status, body })
+        }
+        .await;
+
+        *self.last_outcome.lock().unwrap() = Some((Instant::now(), outcome(&result)));
+        result
+    }
+}
+
+// Not found is a normal answer; server errors mean upstream is down. Errors
+// are described without their URL, which holds the hash prefix of a lookup,
+// as the outcome ends up in the public health check.
+fn outcome(result: &Result<UpstreamResponse, reqwest::Error>) -> Result<(), String> {
+    match result {
+        Ok(response) if response.status >= 500 => Err(format!("upstream answered {}", response.status)),
+        Ok(_) => Ok(()),
+        Err(e) if e.is_timeout() => Err("request timed out".to_string()),
+        Err(e) if e.is_connect() => Err("connection failed".to_string()),
+        Err(e) if e.is_body() || e.is_decode() => Err("failed to read response".to_string()),
+        Err(_) => Err("request failed".to_string()),
+    }
+}
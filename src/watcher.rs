@@ -0,0 +1,93 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::Notify;
+use tracing::{trace, warn};
+
+// Watches the directory of the CSV dump for changes to the dump, to rsync's
+// temporary file next to it, and to the lock file. Notifications only say
+// that something changed; the importer still looks at the file itself, so
+// bursts of events collapse into a single wakeup.
+pub struct FileWatcher {
+    _watcher: RecommendedWatcher,
+    changed: Arc<Notify>,
+}
+
+impl FileWatcher {
+    pub fn new(csv_path: &Path, lock_path: Option<&Path>) -> notify::Result<Self> {
+        let changed = Arc::new(Notify::new());
+        let relevant = RelevantFiles::new(csv_path, lock_path);
+
+        let notify = changed.clone();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+            // Reads, including the importer's own, don't change anything.
+            Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+            Ok(event) => {
+                if event.paths.iter().any(|path| relevant.matches(path)) {
+                    trace!("File change: {:?} {:?}", event.kind, event.paths);
+                    notify.notify_one();
+                }
+            }
+            // Events may have been lost, so have the importer take a look.
+            Err(e) => {
+                warn!("File watcher error: {}", e);
+                notify.notify_one();
+            }
+        })?;
+
+        let mut dirs = vec![parent_dir(csv_path)];
+        if let Some(lock_path) = lock_path {
+            dirs.push(parent_dir(lock_path));
+        }
+        dirs.dedup();
+        for dir in dirs {
+            watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+        }
+
+        Ok(FileWatcher {
+            _watcher: watcher,
+            changed,
+        })
+    }
+
+    // Wait for the next change, returning right away if there was one since
+    // the last call.
+    pub async fn changed(&self) {
+        self.changed.notified().await
+    }
+}
+
+struct RelevantFiles {
+    csv_name: OsString,
+    rsync_prefix: String,
+    lock_name: Option<OsString>,
+}
+
+impl RelevantFiles {
+    fn new(csv_path: &Path, lock_path: Option<&Path>) -> Self {
+        let csv_name = csv_path.file_name().unwrap_or_default().to_os_string();
+        RelevantFiles {
+            rsync_prefix: format!(".{}.", csv_name.to_string_lossy()),
+            csv_name,
+            lock_name: lock_path.and_then(Path::file_name).map(|name| name.to_os_string()),
+        }
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name() else {
+            return false;
+        };
+        name == self.csv_name
+            || name.to_string_lossy().starts_with(&self.rsync_prefix)
+            || self.lock_name.as_deref() == Some(name)
+    }
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}